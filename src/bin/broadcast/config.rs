@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use eyre::{bail, Context};
+use vortice::{
+    topology::{self, Topology},
+    Rng,
+};
+
+/// How often unacknowledged values are sent again when not batching.
+const RETRANSMIT_INTERVAL: Duration = Duration::from_millis(300);
+
+/// How often a node compares digests with one of its peers.
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Knobs for trading latency against message count, read from the
+/// environment since Maelstrom starts the binary without arguments:
+///
+/// - `BROADCAST_BATCH_MS`: instead of gossiping every new value right away,
+///   send everything pending to each neighbour once per interval; `0` keeps
+///   gossiping right away.
+/// - `BROADCAST_FAN_OUT`: at most this many neighbours, and at least one,
+///   are gossiped to per interval, rotating through them.
+/// - `BROADCAST_TOPOLOGY`: `given` (the default) uses the topology message;
+///   `line`, `ring`, `grid`, `tree[:k]`, `star`, `hub[:h]` or `random[:k]`
+///   lay the cluster out from the init node ids and ignore it.
+/// - `BROADCAST_STRATEGY`: `flood` (the default) sends every new value to
+///   every neighbour, `plumtree` only along a self-healing spanning tree of
+///   them and announces it to the rest.
+/// - `BROADCAST_SYNC_MS`: how often to run anti-entropy with one peer, which
+///   repairs whatever gossip missed; `0` turns it off.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub batch: Option<Duration>,
+    pub fan_out: Option<usize>,
+    pub layout: Layout,
+    pub strategy: Strategy,
+    pub sync: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            batch: None,
+            fan_out: None,
+            layout: Layout::Given,
+            strategy: Strategy::Flood,
+            sync: Some(SYNC_INTERVAL),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Given,
+    Line,
+    Ring,
+    Grid,
+    Tree(usize),
+    Star,
+    HubAndSpoke(usize),
+    RandomRegular(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Flood,
+    Plumtree,
+}
+
+impl Config {
+    pub fn from_env() -> eyre::Result<Self> {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+
+        let mut config = Config::default();
+        if let Some(ms) = var("BROADCAST_BATCH_MS") {
+            let ms = ms.parse().context("BROADCAST_BATCH_MS")?;
+            config.batch = Some(Duration::from_millis(ms)).filter(|d| !d.is_zero());
+        }
+        if let Some(fan_out) = var("BROADCAST_FAN_OUT") {
+            let fan_out = fan_out.parse().context("BROADCAST_FAN_OUT")?;
+            if fan_out == 0 {
+                bail!("BROADCAST_FAN_OUT must be at least 1");
+            }
+            config.fan_out = Some(fan_out);
+        }
+        if let Some(layout) = var("BROADCAST_TOPOLOGY") {
+            config.layout = layout.parse().context("BROADCAST_TOPOLOGY")?;
+        }
+        if let Some(strategy) = var("BROADCAST_STRATEGY") {
+            config.strategy = match strategy.as_str() {
+                "flood" => Strategy::Flood,
+                "plumtree" => Strategy::Plumtree,
+                other => bail!("unknown strategy {other}, expected flood or plumtree"),
+            };
+        }
+        if let Some(ms) = var("BROADCAST_SYNC_MS") {
+            let ms = ms.parse().context("BROADCAST_SYNC_MS")?;
+            config.sync = Some(Duration::from_millis(ms)).filter(|d| !d.is_zero());
+        }
+        Ok(config)
+    }
+
+    /// The gossip timer's period.
+    pub fn interval(&self) -> Duration {
+        self.batch.unwrap_or(RETRANSMIT_INTERVAL)
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        let (name, arg) = match s.split_once(':') {
+            Some((name, arg)) => (name, Some(arg.parse::<usize>().context(s.to_owned())?)),
+            None => (s, None),
+        };
+        Ok(match (name, arg) {
+            ("given", None) => Layout::Given,
+            ("line", None) => Layout::Line,
+            ("ring", None) => Layout::Ring,
+            ("grid", None) => Layout::Grid,
+            ("tree", Some(0)) => bail!("{s} has no children, expected tree:k with k > 0"),
+            ("tree", k) => Layout::Tree(k.unwrap_or(4)),
+            ("star", None) => Layout::Star,
+            ("hub", h) => Layout::HubAndSpoke(h.unwrap_or(2)),
+            ("random", k) => Layout::RandomRegular(k.unwrap_or(4)),
+            _ => bail!(
+                "unknown topology {s}, expected given, line, ring, grid, tree[:k], star, \
+                 hub[:h] or random[:k]"
+            ),
+        })
+    }
+}
+
+impl Layout {
+    /// The layout to use instead of the topology message, if any. Every node
+    /// builds it on its own, so random layouts use a fixed seed.
+    pub fn build(self, node_ids: &[String]) -> eyre::Result<Option<Topology>> {
+        Ok(Some(match self {
+            Layout::Given => return Ok(None),
+            Layout::Line => topology::line(node_ids),
+            Layout::Ring => topology::ring(node_ids),
+            Layout::Grid => topology::grid(node_ids),
+            Layout::Tree(k) => topology::tree(node_ids, k)?,
+            Layout::Star => topology::star(node_ids),
+            Layout::HubAndSpoke(hubs) => topology::hub_and_spoke(node_ids, hubs),
+            Layout::RandomRegular(k) => topology::random_regular(node_ids, k, &mut Rng::new(0))?,
+        }))
+    }
+}
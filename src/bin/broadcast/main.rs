@@ -0,0 +1,702 @@
+mod config;
+mod plumtree;
+mod runs;
+mod store;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use vortice::{main_loop, topology, Ctx, Event, Init, Msg, Node, RpcError};
+
+use config::{Config, Layout, Strategy};
+use plumtree::Plumtree;
+use runs::Runs;
+use store::{Bucket, Store};
+
+/// How long to wait for a neighbour to acknowledge gossip before sending the
+/// values again, and for a peer to answer a sync. Independent of the gossip
+/// period, and well above a round trip even at Maelstrom's highest latencies.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long an ack may still come in after its gossip timed out; the values
+/// have been sent again several times over by then.
+const ACK_HORIZON: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    /// Values passed between nodes, acknowledged with `gossip_ok`.
+    Gossip {
+        messages: Runs,
+    },
+    GossipOk,
+    /// Anti-entropy: a summary of every value the sender holds.
+    Sync {
+        digest: Vec<Bucket>,
+    },
+    /// The buckets that did not match, with the values the receiver holds in
+    /// them, so the sender can work out what each side is missing.
+    SyncOk {
+        buckets: Vec<usize>,
+        messages: Runs,
+    },
+    /// Plumtree: values pushed along the tree, acknowledged with
+    /// `gossip_ok`, or with `prune` if they were nothing new.
+    Push {
+        messages: Runs,
+    },
+    /// Plumtree: values the sender has, for peers off the tree; acknowledged
+    /// with `gossip_ok`.
+    IHave {
+        messages: Runs,
+    },
+    /// Plumtree: the reply to a push of nothing new; stop pushing to the
+    /// sender.
+    Prune,
+    /// Plumtree: push to the sender again, starting with these values.
+    Graft {
+        messages: Runs,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Timer {
+    /// Sends whatever neighbours have not acknowledged yet: new values when
+    /// batching, and values whose gossip timed out.
+    Gossip,
+    Sync,
+}
+
+struct BroadcastNode {
+    config: Config,
+    messages: Store,
+    /// Who newly seen values are forwarded to. Everyone until the topology
+    /// message says otherwise, unless the config picks a layout itself.
+    neighbours: Vec<String>,
+    /// Values each neighbour has not acknowledged yet, pushed or announced.
+    unacked: BTreeMap<String, BTreeSet<usize>>,
+    /// Gossip awaiting its ack, by msg_id, so that a reply arriving after its
+    /// timeout still counts.
+    in_flight: BTreeMap<usize, Sent>,
+    /// Gossip rounds so far, to rotate which neighbours a limited fan-out
+    /// picks.
+    round: usize,
+    /// Anti-entropy rounds so far, to rotate through the peers.
+    syncs: usize,
+    /// Which neighbours get pushed what, with the plumtree strategy.
+    plumtree: Option<Plumtree>,
+}
+
+/// One gossip message: who it went to, with which values, when.
+struct Sent {
+    peer: String,
+    messages: Vec<usize>,
+    at: Instant,
+}
+
+impl BroadcastNode {
+    /// Stores `messages` and passes the ones that are new on to every
+    /// neighbour but `from`.
+    fn accept(
+        &mut self,
+        from: &str,
+        messages: impl IntoIterator<Item = usize>,
+        ctx: &mut Ctx<Self, Timer>,
+    ) -> eyre::Result<()> {
+        let new: Vec<usize> = messages
+            .into_iter()
+            .filter(|m| self.messages.insert(*m))
+            .collect();
+        if new.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(tree) = &mut self.plumtree {
+            tree.deliver(&new);
+        }
+
+        let neighbours: Vec<String> = self
+            .neighbours
+            .iter()
+            .filter(|n| *n != from)
+            .cloned()
+            .collect();
+        for neighbour in neighbours {
+            self.unacked
+                .entry(neighbour.clone())
+                .or_default()
+                .extend(&new);
+            // Announcements always wait for the next tick, to go out in
+            // batches.
+            if self.config.batch.is_none() && !self.is_lazy(&neighbour) {
+                self.gossip(neighbour, new.clone(), ctx)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_lazy(&self, peer: &str) -> bool {
+        self.plumtree
+            .as_ref()
+            .is_some_and(|tree| tree.is_lazy(peer))
+    }
+
+    /// Sends `neighbour` the given values it has not acknowledged: pushed or
+    /// announced along the tree with the plumtree strategy.
+    fn gossip(
+        &mut self,
+        neighbour: String,
+        messages: Vec<usize>,
+        ctx: &mut Ctx<Self, Timer>,
+    ) -> eyre::Result<()> {
+        let runs = messages.iter().copied().collect();
+        let payload = match &self.plumtree {
+            None => Payload::Gossip { messages: runs },
+            Some(_) if self.is_lazy(&neighbour) => Payload::IHave { messages: runs },
+            Some(_) => Payload::Push { messages: runs },
+        };
+        let msg_id = ctx
+            .rpc(
+                neighbour.clone(),
+                payload,
+                ACK_TIMEOUT,
+                |node: &mut Self, reply: Result<Msg<Payload>, RpcError>, _ctx| {
+                    // Without a reply the values go out again on a later
+                    // tick, and the reply may yet turn up as a plain message.
+                    if let Ok(reply) = reply {
+                        node.acked(&reply);
+                    }
+                    Ok(())
+                },
+            )
+            .context("gossip")?;
+        self.in_flight.insert(
+            msg_id,
+            Sent {
+                peer: neighbour,
+                messages,
+                at: ctx.now(),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// The gossip `reply` answers got through.
+    fn acked(&mut self, reply: &Msg<Payload>) {
+        if let (Payload::Prune, Some(tree)) = (&reply.body.payload, &mut self.plumtree) {
+            tree.prune(&reply.src);
+        }
+        let Some(sent) = reply
+            .body
+            .in_reply_to
+            .and_then(|id| self.in_flight.remove(&id))
+        else {
+            return;
+        };
+        if let Some(unacked) = self.unacked.get_mut(&sent.peer) {
+            for m in &sent.messages {
+                unacked.remove(m);
+            }
+        }
+    }
+
+    fn flush(&mut self, ctx: &mut Ctx<Self, Timer>) -> eyre::Result<()> {
+        if let Some(tree) = &mut self.plumtree {
+            // Grafts are sent again for as long as the values are missing.
+            let grafts = tree.take_overdue(ctx.now(), self.config.interval());
+            for (peer, values) in grafts {
+                eprintln!(
+                    "{}: grafting {peer} for {} values",
+                    ctx.node_id(),
+                    values.len()
+                );
+                let messages = values.into_iter().collect();
+                ctx.send(peer, Payload::Graft { messages })
+                    .context("graft")?;
+            }
+        }
+
+        // Values still waiting on an ack within its timeout are not due yet.
+        let now = ctx.now();
+        self.in_flight
+            .retain(|_, sent| now.duration_since(sent.at) < ACK_HORIZON);
+        let mut waiting: BTreeSet<(&str, usize)> = BTreeSet::new();
+        for sent in self.in_flight.values() {
+            if now.duration_since(sent.at) < ACK_TIMEOUT {
+                waiting.extend(sent.messages.iter().map(|m| (sent.peer.as_str(), *m)));
+            }
+        }
+        let mut pending: Vec<(String, Vec<usize>)> = self
+            .unacked
+            .iter()
+            .map(|(peer, messages)| {
+                let due: Vec<usize> = messages
+                    .iter()
+                    .filter(|m| !waiting.contains(&(peer.as_str(), **m)))
+                    .copied()
+                    .collect();
+                (peer.clone(), due)
+            })
+            .filter(|(_, due)| !due.is_empty())
+            .collect();
+        if let Some(fan_out) = self.config.fan_out {
+            if !pending.is_empty() {
+                let len = pending.len();
+                pending.rotate_left(self.round % len);
+                pending.truncate(fan_out);
+            }
+        }
+        self.round += 1;
+
+        for (peer, messages) in pending {
+            self.gossip(peer, messages, ctx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts an anti-entropy round with the next peer in line.
+    fn sync(&mut self, ctx: &mut Ctx<Self, Timer>) -> eyre::Result<()> {
+        let peers: Vec<String> = ctx.peers().map(String::from).collect();
+        if peers.is_empty() {
+            return Ok(());
+        }
+        let peer = peers[self.syncs % peers.len()].clone();
+        self.syncs += 1;
+
+        let digest = self.messages.digest();
+        ctx.rpc(
+            peer,
+            Payload::Sync { digest },
+            ACK_TIMEOUT,
+            |node: &mut Self, reply: Result<Msg<Payload>, RpcError>, ctx| {
+                // A lost round is simply made up for by the next one, and a
+                // late reply is still reconciled when it turns up.
+                let Ok(reply) = reply else {
+                    return Ok(());
+                };
+                match reply.body.payload {
+                    Payload::SyncOk { buckets, messages } => {
+                        node.reconcile(&reply.src, &buckets, messages, ctx)
+                    }
+                    _ => Ok(()),
+                }
+            },
+        )
+        .context("sync")?;
+
+        Ok(())
+    }
+
+    /// Answers a digest with our side of every bucket that differs.
+    fn compare(&self, digest: &[Bucket]) -> Payload {
+        let theirs: BTreeMap<usize, &Bucket> = digest.iter().map(|b| (b.start, b)).collect();
+        let mine = self.messages.digest();
+        let mine: BTreeMap<usize, &Bucket> = mine.iter().map(|b| (b.start, b)).collect();
+
+        let buckets: Vec<usize> = theirs
+            .keys()
+            .chain(mine.keys())
+            .copied()
+            .collect::<BTreeSet<usize>>()
+            .into_iter()
+            .filter(|start| theirs.get(start) != mine.get(start))
+            .collect();
+        let messages = buckets
+            .iter()
+            .flat_map(|start| self.messages.bucket(*start))
+            .collect();
+        Payload::SyncOk { buckets, messages }
+    }
+
+    /// Takes in what `peer` had in the buckets that differed and sends it
+    /// back whatever it lacks there.
+    fn reconcile(
+        &mut self,
+        peer: &str,
+        buckets: &[usize],
+        messages: Runs,
+        ctx: &mut Ctx<Self, Timer>,
+    ) -> eyre::Result<()> {
+        let theirs: BTreeSet<usize> = messages.iter().collect();
+        let missing: Vec<usize> = buckets
+            .iter()
+            .flat_map(|start| self.messages.bucket(*start))
+            .filter(|m| !theirs.contains(m))
+            .collect();
+
+        let before = self.messages.len();
+        self.accept(peer, messages.iter(), ctx)?;
+        let learned = self.messages.len() - before;
+        if learned > 0 || !missing.is_empty() {
+            eprintln!(
+                "{}: sync with {peer}: learned {learned}, sending {}",
+                ctx.node_id(),
+                missing.len()
+            );
+        }
+
+        if !missing.is_empty() {
+            self.unacked
+                .entry(peer.to_owned())
+                .or_default()
+                .extend(&missing);
+            self.gossip(peer.to_owned(), missing, ctx)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node<Config, Payload, Timer> for BroadcastNode {
+    fn from_init(config: Config, _init: Init, ctx: &mut Ctx<Self, Timer>) -> eyre::Result<Self> {
+        ctx.every(config.interval(), Timer::Gossip);
+        if let Some(period) = config.sync {
+            ctx.every(period, Timer::Sync);
+        }
+
+        let neighbours = match config.layout.build(ctx.node_ids())? {
+            Some(topology) => {
+                eprintln!(
+                    "{}: {:?} layout, diameter {:?}, max degree {}, min cut {}",
+                    ctx.node_id(),
+                    config.layout,
+                    topology::diameter(&topology),
+                    topology::max_degree(&topology),
+                    topology::min_cut(&topology)
+                );
+                topology.get(ctx.node_id()).cloned().unwrap_or_default()
+            }
+            None => ctx.peers().map(String::from).collect(),
+        };
+        eprintln!("{}: gossiping to {neighbours:?}", ctx.node_id());
+        let plumtree = (config.strategy == Strategy::Plumtree).then(|| Plumtree::new(&neighbours));
+
+        Ok(BroadcastNode {
+            config,
+            messages: Store::default(),
+            neighbours,
+            unacked: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+            round: 0,
+            syncs: 0,
+            plumtree,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, Timer>,
+        ctx: &mut Ctx<Self, Timer>,
+    ) -> eyre::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Timer(Timer::Gossip) => return self.flush(ctx),
+            Event::Timer(Timer::Sync) => return self.sync(ctx),
+            Event::Eof => {
+                eprintln!("{}: holding {} values", ctx.node_id(), self.messages.len());
+                return Ok(());
+            }
+        };
+
+        match &input.body.payload {
+            Payload::Broadcast { message } => {
+                self.accept(&input.src, [*message], ctx)?;
+
+                ctx.reply(&input, Payload::BroadcastOk)
+                    .context("reply to broadcast")?;
+            }
+            Payload::Gossip { messages } => {
+                self.accept(&input.src, messages.iter(), ctx)?;
+
+                ctx.reply(&input, Payload::GossipOk)
+                    .context("reply to gossip")?;
+            }
+            Payload::Push { messages } => {
+                let before = self.messages.len();
+                self.accept(&input.src, messages.iter(), ctx)?;
+
+                let reply = if self.messages.len() == before {
+                    if let Some(tree) = &mut self.plumtree {
+                        tree.prune(&input.src);
+                    }
+                    Payload::Prune
+                } else {
+                    Payload::GossipOk
+                };
+                ctx.reply(&input, reply).context("reply to push")?;
+            }
+            Payload::IHave { messages } => {
+                if let Some(tree) = &mut self.plumtree {
+                    let store = &self.messages;
+                    let missing = messages.iter().filter(|m| !store.contains(*m));
+                    tree.announced(&input.src, missing, ctx.now());
+                }
+
+                ctx.reply(&input, Payload::GossipOk)
+                    .context("reply to i_have")?;
+            }
+            Payload::Graft { messages } => {
+                if let Some(tree) = &mut self.plumtree {
+                    tree.graft(&input.src);
+                }
+
+                let held: Vec<usize> = messages
+                    .iter()
+                    .filter(|m| self.messages.contains(*m))
+                    .collect();
+                if !held.is_empty() {
+                    self.unacked
+                        .entry(input.src.clone())
+                        .or_default()
+                        .extend(&held);
+                    self.gossip(input.src.clone(), held, ctx)?;
+                }
+            }
+            Payload::Sync { digest } => {
+                let reply = self.compare(digest);
+                ctx.reply(&input, reply).context("reply to sync")?;
+            }
+            Payload::Read => {
+                let messages = self.messages.to_vec();
+                ctx.reply(&input, Payload::ReadOk { messages })
+                    .context("reply to read")?;
+            }
+            Payload::Topology { topology } => {
+                if self.config.layout == Layout::Given {
+                    if let Some(neighbours) = topology.get(ctx.node_id()) {
+                        self.neighbours = neighbours.clone();
+                        if self.plumtree.is_some() {
+                            self.plumtree = Some(Plumtree::new(neighbours));
+                        }
+                    }
+                }
+
+                ctx.reply(&input, Payload::TopologyOk)
+                    .context("reply to topology")?;
+            }
+            // Acks that came in after their rpc timed out.
+            Payload::GossipOk | Payload::Prune => self.acked(&input),
+            Payload::SyncOk { buckets, messages } => {
+                self.reconcile(&input.src, buckets, messages.clone(), ctx)?;
+            }
+            Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    let config = Config::from_env()?;
+    main_loop::<_, BroadcastNode, _, _>(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use vortice::sim::{Fault, Record, Sim, SimConfig};
+
+    use super::*;
+
+    type Cluster = Sim<Config, BroadcastNode, Payload, Timer>;
+
+    fn cluster(config: Config, n: usize, latency: Duration, loss: f64) -> Cluster {
+        let sim = SimConfig {
+            seed: 1,
+            min_latency: latency,
+            max_latency: latency,
+            loss,
+        };
+        Sim::new(sim, n, config).unwrap()
+    }
+
+    /// Broadcasts `0..count` round-robin over the nodes, `gap` apart.
+    fn broadcast(sim: &mut Cluster, count: usize, gap: Duration) {
+        let ids: Vec<String> = sim.node_ids().map(String::from).collect();
+        for message in 0..count {
+            let node = &ids[message % ids.len()];
+            sim.request("c1", node, Payload::Broadcast { message })
+                .unwrap();
+            sim.run_for(gap).unwrap();
+        }
+    }
+
+    /// How many values each node holds.
+    fn holdings(sim: &Cluster) -> Vec<usize> {
+        sim.node_ids()
+            .map(|id| sim.node(id).unwrap().messages.len())
+            .collect()
+    }
+
+    fn unacked(sim: &Cluster) -> usize {
+        sim.node_ids()
+            .flat_map(|id| sim.node(id).unwrap().unacked.values())
+            .map(BTreeSet::len)
+            .sum()
+    }
+
+    /// Messages of the given type nodes sent to each other since `since`.
+    fn sent(sim: &Cluster, kind: &str, since: Duration) -> usize {
+        sim.history()
+            .iter()
+            .filter(|record| match record {
+                Record::Sent { at, msg } => {
+                    *at >= since && msg.dst.starts_with('n') && msg.body.payload["type"] == kind
+                }
+                _ => false,
+            })
+            .count()
+    }
+
+    /// How many values went out in gossip messages altogether.
+    fn gossiped(sim: &Cluster) -> usize {
+        sim.history()
+            .iter()
+            .filter_map(|record| match record {
+                Record::Sent { msg, .. } if msg.body.payload["type"] == "gossip" => {
+                    let messages = msg.body.payload["messages"].clone();
+                    Some(
+                        serde_json::from_value::<Runs>(messages)
+                            .unwrap()
+                            .iter()
+                            .count(),
+                    )
+                }
+                _ => None,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn batches_get_acknowledged_at_high_latency() {
+        let config = Config {
+            batch: Some(Duration::from_millis(100)),
+            ..Config::default()
+        };
+        let mut sim = cluster(config, 5, Duration::from_millis(100), 0.0);
+        broadcast(&mut sim, 50, Duration::from_millis(10));
+        sim.run_for(Duration::from_secs(3)).unwrap();
+
+        assert_eq!(holdings(&sim), [50; 5]);
+        assert_eq!(unacked(&sim), 0);
+        let quiet = sim.now() - Duration::from_secs(1);
+        assert_eq!(sent(&sim, "gossip", quiet), 0, "idle cluster is quiet");
+    }
+
+    #[test]
+    fn retransmits_only_after_the_ack_timeout() {
+        // Round trips above the retransmit interval, and with random
+        // latencies some above the ack timeout too, whose acks arrive after
+        // the gossip was sent again.
+        for (min, max) in [(400, 400), (1, 900)] {
+            let sim = SimConfig {
+                seed: 1,
+                min_latency: Duration::from_millis(min),
+                max_latency: Duration::from_millis(max),
+                loss: 0.0,
+            };
+            let mut sim: Cluster = Sim::new(sim, 5, Config::default()).unwrap();
+            broadcast(&mut sim, 50, Duration::from_millis(10));
+            sim.run_for(Duration::from_secs(5)).unwrap();
+
+            assert_eq!(holdings(&sim), [50; 5], "{max}ms");
+            assert_eq!(unacked(&sim), 0, "{max}ms");
+            let quiet = sim.now() - Duration::from_secs(1);
+            assert_eq!(sent(&sim, "gossip", quiet), 0, "{max}ms");
+            // Each value goes to each of 4 neighbours of 5 nodes at most
+            // twice: once, and once more if the ack is late.
+            let gossiped = gossiped(&sim);
+            assert!(gossiped <= 2 * 50 * 5 * 4, "{max}ms: {gossiped} values");
+        }
+    }
+
+    #[test]
+    fn anti_entropy_alone_spreads_every_value() {
+        // Sync replies take longer than the gossip period, and with no
+        // neighbours values are only ever passed on by syncing.
+        let config = Config {
+            batch: Some(Duration::from_millis(100)),
+            ..Config::default()
+        };
+        let mut sim = cluster(config, 5, Duration::from_millis(100), 0.0);
+        let ids: Vec<String> = sim.node_ids().map(String::from).collect();
+        let topology: HashMap<String, Vec<String>> =
+            ids.iter().map(|id| (id.clone(), Vec::new())).collect();
+        for id in &ids {
+            let topology = topology.clone();
+            sim.request("c1", id, Payload::Topology { topology })
+                .unwrap();
+        }
+        sim.run_for(Duration::from_millis(500)).unwrap();
+        for id in &ids {
+            assert!(sim.node(id).unwrap().neighbours.is_empty(), "{id}");
+        }
+        broadcast(&mut sim, 20, Duration::from_millis(10));
+        sim.run_for(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(holdings(&sim), [20; 5]);
+    }
+
+    fn plumtree_grid(loss: f64) -> Cluster {
+        let config = Config {
+            layout: Layout::Grid,
+            strategy: Strategy::Plumtree,
+            sync: None,
+            ..Config::default()
+        };
+        let sim = SimConfig {
+            seed: 2,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(20),
+            loss,
+        };
+        Sim::new(sim, 9, config).unwrap()
+    }
+
+    #[test]
+    fn plumtree_heals_through_loss() {
+        let mut sim = plumtree_grid(0.2);
+        broadcast(&mut sim, 100, Duration::from_millis(10));
+        sim.run_for(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(holdings(&sim), [100; 9]);
+        assert_eq!(unacked(&sim), 0);
+        let quiet = sim.now() - Duration::from_secs(2);
+        for kind in ["push", "i_have", "graft"] {
+            assert_eq!(sent(&sim, kind, quiet), 0, "no {kind} once settled");
+        }
+    }
+
+    #[test]
+    fn plumtree_heals_after_a_partition() {
+        let mut sim = plumtree_grid(0.0);
+        sim.apply(Fault::PartitionHalves).unwrap();
+        broadcast(&mut sim, 100, Duration::from_millis(10));
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        assert!(holdings(&sim).iter().all(|held| *held < 100), "partitioned");
+        sim.apply(Fault::Heal).unwrap();
+        sim.run_for(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(holdings(&sim), [100; 9]);
+        assert_eq!(unacked(&sim), 0);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, Instant},
+};
+
+/// The bookkeeping for Plumtree (Leitão et al., "Epidemic Broadcast Trees"):
+/// new values are pushed eagerly along a spanning tree and only announced
+/// (`i_have`) to every other neighbour. The tree starts out as every link,
+/// loses the links that deliver duplicates (`prune`), and regains one
+/// whenever an announced value does not show up in time (`graft`).
+///
+/// This only decides who gets what; the node does the sending, and resends
+/// pushes and announcements until they are acknowledged like any gossip.
+#[derive(Debug, Default)]
+pub struct Plumtree {
+    eager: BTreeSet<String>,
+    lazy: BTreeSet<String>,
+    /// Values we heard of but do not have yet.
+    missing: BTreeMap<usize, Missing>,
+}
+
+#[derive(Debug)]
+struct Missing {
+    /// Who announced the value, in the order they did.
+    announcers: Vec<String>,
+    /// When it was announced or last grafted for.
+    since: Instant,
+    grafts: usize,
+}
+
+impl Plumtree {
+    pub fn new(neighbours: &[String]) -> Self {
+        Plumtree {
+            eager: neighbours.iter().cloned().collect(),
+            ..Plumtree::default()
+        }
+    }
+
+    /// Whether values go to `peer` as announcements rather than in full.
+    pub fn is_lazy(&self, peer: &str) -> bool {
+        self.lazy.contains(peer)
+    }
+
+    /// The values arrived, so there is no more need to graft for them.
+    pub fn deliver(&mut self, values: &[usize]) {
+        for value in values {
+            self.missing.remove(value);
+        }
+    }
+
+    /// Moves `peer` off the tree, after it sent us something twice or asked
+    /// us to stop.
+    pub fn prune(&mut self, peer: &str) {
+        if self.eager.remove(peer) {
+            self.lazy.insert(peer.to_owned());
+        }
+    }
+
+    /// Puts `peer` (back) on the tree.
+    pub fn graft(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.to_owned());
+    }
+
+    /// `peer` has the given values we lack.
+    pub fn announced(&mut self, peer: &str, values: impl IntoIterator<Item = usize>, now: Instant) {
+        for value in values {
+            let missing = self.missing.entry(value).or_insert_with(|| Missing {
+                announcers: Vec::new(),
+                since: now,
+                grafts: 0,
+            });
+            if !missing.announcers.iter().any(|a| a == peer) {
+                missing.announcers.push(peer.to_owned());
+            }
+        }
+    }
+
+    /// Values that were announced or grafted for at least `timeout` ago and
+    /// still have not arrived, by the peer to graft for them: each time the
+    /// next of the peers that announced them, in case one is out of reach.
+    /// Those peers are grafted back onto the tree, since the tree evidently
+    /// failed us. Values stay missing until they arrive, so they are grafted
+    /// for again every `timeout`.
+    pub fn take_overdue(
+        &mut self,
+        now: Instant,
+        timeout: Duration,
+    ) -> BTreeMap<String, Vec<usize>> {
+        let mut grafts: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (value, missing) in &mut self.missing {
+            if now.duration_since(missing.since) < timeout {
+                continue;
+            }
+            let peer = &missing.announcers[missing.grafts % missing.announcers.len()];
+            grafts.entry(peer.clone()).or_default().push(*value);
+            missing.since = now;
+            missing.grafts += 1;
+        }
+        for peer in grafts.keys() {
+            self.graft(peer);
+        }
+        grafts
+    }
+}
@@ -0,0 +1,139 @@
+use std::ops::RangeInclusive;
+
+use eyre::{bail, eyre};
+use serde::{Deserialize, Serialize};
+
+/// A set of values as runs of consecutive integers, which is how they travel
+/// between nodes: broadcast values tend to be dense, so this stays small
+/// where a plain array would grow with every value.
+///
+/// On the wire it is a flat array of pairs, each run given as its distance
+/// from the end of the previous run and its length, so `[1, 2, 3, 7, 8]` is
+/// `[1, 3, 3, 2]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<usize>", into = "Vec<usize>")]
+pub struct Runs {
+    /// Inclusive, so a run can end at `usize::MAX`.
+    runs: Vec<RangeInclusive<usize>>,
+}
+
+impl Runs {
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    /// The values in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.runs.iter().flat_map(RangeInclusive::clone)
+    }
+}
+
+impl FromIterator<usize> for Runs {
+    /// Values may come in any order and more than once.
+    fn from_iter<I: IntoIterator<Item = usize>>(values: I) -> Self {
+        let mut values: Vec<usize> = values.into_iter().collect();
+        values.sort_unstable();
+        values.dedup();
+
+        let mut runs: Vec<RangeInclusive<usize>> = Vec::new();
+        for value in values {
+            match runs.last_mut() {
+                Some(run) if run.end().checked_add(1) == Some(value) => {
+                    *run = *run.start()..=value;
+                }
+                _ => runs.push(value..=value),
+            }
+        }
+        Runs { runs }
+    }
+}
+
+impl From<Runs> for Vec<usize> {
+    fn from(runs: Runs) -> Self {
+        let mut encoded = Vec::with_capacity(runs.runs.len() * 2);
+        // Where the previous run ended, exclusive. A run ending at
+        // `usize::MAX` is the last one, so this never overflows when used.
+        let mut end = 0;
+        for run in runs.runs {
+            let (start, last) = run.into_inner();
+            encoded.push(start - end);
+            // Cannot overflow: a set holding every `usize` does not fit in
+            // memory.
+            encoded.push(last - start + 1);
+            end = last.wrapping_add(1);
+        }
+        encoded
+    }
+}
+
+impl TryFrom<Vec<usize>> for Runs {
+    type Error = eyre::Report;
+
+    fn try_from(encoded: Vec<usize>) -> eyre::Result<Self> {
+        if !encoded.len().is_multiple_of(2) {
+            bail!(
+                "runs come in (gap, length) pairs, got {} numbers",
+                encoded.len()
+            );
+        }
+
+        let mut runs: Vec<RangeInclusive<usize>> = Vec::with_capacity(encoded.len() / 2);
+        for pair in encoded.chunks_exact(2) {
+            let (gap, len) = (pair[0], pair[1]);
+            if len == 0 || (gap == 0 && !runs.is_empty()) {
+                bail!("runs must be non-empty and apart, got gap {gap} length {len}");
+            }
+            let end = match runs.last() {
+                Some(run) => run.end().checked_add(1),
+                None => Some(0),
+            };
+            let start = end
+                .and_then(|end| end.checked_add(gap))
+                .ok_or_else(|| eyre!("run start overflows"))?;
+            let last = start
+                .checked_add(len - 1)
+                .ok_or_else(|| eyre!("run end overflows"))?;
+            runs.push(start..=last);
+        }
+        Ok(Runs { runs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(values: &[usize]) -> Vec<usize> {
+        let runs: Runs = values.iter().copied().collect();
+        let json = serde_json::to_string(&runs).unwrap();
+        let decoded: Runs = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, runs, "{json}");
+        decoded.iter().collect()
+    }
+
+    #[test]
+    fn round_trips_exactly() {
+        let max = usize::MAX;
+        assert_eq!(round_trip(&[]), Vec::<usize>::new());
+        assert_eq!(round_trip(&[0]), [0]);
+        assert_eq!(round_trip(&[max]), [max]);
+        assert_eq!(round_trip(&[max, 0, max - 1]), [0, max - 1, max]);
+        assert_eq!(round_trip(&[3, 1, 2, 2, 8, 7, 1]), [1, 2, 3, 7, 8]);
+        assert_eq!(round_trip(&[0, 1, max - 2, max]), [0, 1, max - 2, max]);
+    }
+
+    #[test]
+    fn encodes_gaps_and_lengths() {
+        let runs: Runs = [1, 2, 3, 7, 8].into_iter().collect();
+        assert_eq!(Vec::from(runs), [1, 3, 3, 2]);
+    }
+
+    #[test]
+    fn rejects_overflowing_runs() {
+        let max = usize::MAX;
+        assert!(Runs::try_from(vec![max, 2]).is_err());
+        assert!(Runs::try_from(vec![max, 1, 1, 1]).is_err());
+        assert!(Runs::try_from(vec![1, 0]).is_err());
+        assert!(Runs::try_from(vec![0, 1, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use vortice::crdt::GSet;
+
+/// How many consecutive values one [`Bucket`] of a digest covers.
+pub const BUCKET_WIDTH: usize = 64;
+
+/// A summary of the values a store holds in `start..start + BUCKET_WIDTH`,
+/// for telling cheaply whether two stores agree on that range.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bucket {
+    pub start: usize,
+    pub count: usize,
+    pub hash: u64,
+}
+
+/// Every broadcast value a node has seen, each kept once and handed out in
+/// ascending order: a grow-only set, with digests for anti-entropy on top.
+#[derive(Debug, Default)]
+pub struct Store {
+    values: GSet<usize>,
+}
+
+impl Store {
+    /// Returns whether `value` was new to the store.
+    pub fn insert(&mut self, value: usize) -> bool {
+        self.values.insert(value)
+    }
+
+    pub fn contains(&self, value: usize) -> bool {
+        self.values.contains(&value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.values.iter().copied()
+    }
+
+    pub fn to_vec(&self) -> Vec<usize> {
+        self.iter().collect()
+    }
+
+    /// One bucket per range the store holds anything in, in order.
+    pub fn digest(&self) -> Vec<Bucket> {
+        let mut digest: Vec<Bucket> = Vec::new();
+        for value in self.iter() {
+            let start = value - value % BUCKET_WIDTH;
+            match digest.last_mut() {
+                Some(bucket) if bucket.start == start => {
+                    bucket.count += 1;
+                    bucket.hash ^= mix(value);
+                }
+                _ => digest.push(Bucket {
+                    start,
+                    count: 1,
+                    hash: mix(value),
+                }),
+            }
+        }
+        digest
+    }
+
+    /// The values in the bucket starting at `start`.
+    pub fn bucket(&self, start: usize) -> impl Iterator<Item = usize> + '_ {
+        self.values
+            .elements()
+            .range(start..start.saturating_add(BUCKET_WIDTH))
+            .copied()
+    }
+}
+
+/// SplitMix64's finalizer, so that xor-ing values together makes a usable
+/// order-independent hash of a set.
+fn mix(value: usize) -> u64 {
+    let mut z = (value as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,39 @@
+use vortice::{
+    crdt::{Crdt, GCounter, PnCounter},
+    Error, ErrorCode,
+};
+
+/// What the node needs of a counter beyond merging it: the workloads only
+/// ever add signed amounts and read back the total.
+pub trait Counter: Crdt {
+    /// The change adding `by` through `node` makes, for the replicator.
+    fn add_delta(&self, node: &str, by: i64) -> Result<Self, Error>;
+
+    fn value(&self) -> i64;
+}
+
+impl Counter for GCounter {
+    fn add_delta(&self, node: &str, by: i64) -> Result<Self, Error> {
+        let by = u64::try_from(by).map_err(|_| {
+            Error::new(
+                ErrorCode::MalformedRequest,
+                format!("a grow-only counter cannot add {by}"),
+            )
+        })?;
+        Ok(self.increment_delta(node, by))
+    }
+
+    fn value(&self) -> i64 {
+        GCounter::value(self) as i64
+    }
+}
+
+impl Counter for PnCounter {
+    fn add_delta(&self, node: &str, by: i64) -> Result<Self, Error> {
+        Ok(PnCounter::add_delta(self, node, by))
+    }
+
+    fn value(&self) -> i64 {
+        PnCounter::value(self)
+    }
+}
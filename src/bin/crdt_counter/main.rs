@@ -0,0 +1,189 @@
+//! A counter kept without any service: every node counts what was added
+//! through it and periodically sends each peer the changes it has not
+//! acknowledged, which the peer merges into its copy. A node only takes
+//! adds once it has every peer's copy, since after a restart that lost its
+//! own it would otherwise count up from 0 again. Serves both the
+//! `g-counter` and `pn-counter` workloads, picked with `CRDT_COUNTER=g` or
+//! `CRDT_COUNTER=pn` (the default).
+
+mod counter;
+
+use std::time::Duration;
+
+use eyre::{bail, Context};
+use serde::{Deserialize, Serialize};
+use vortice::{
+    crdt::{Ack, Delta, GCounter, PnCounter, Replicator},
+    main_loop, Ctx, Error, ErrorCode, Event, Init, Node,
+};
+
+use counter::Counter;
+
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "C: Counter")]
+enum Payload<C> {
+    Add {
+        delta: i64,
+    },
+    AddOk,
+    Read,
+    ReadOk {
+        value: i64,
+    },
+    /// What the sender changed since the recipient's last ack.
+    Gossip(Delta<C>),
+    GossipOk(Ack),
+}
+
+#[derive(Debug, Clone)]
+enum Timer {
+    Gossip,
+}
+
+struct CounterNode<C> {
+    counter: Replicator<C>,
+}
+
+impl<C: Counter> Node<(), Payload<C>, Timer> for CounterNode<C> {
+    fn from_init(_state: (), _init: Init, ctx: &mut Ctx<Self, Timer>) -> eyre::Result<Self> {
+        ctx.every(GOSSIP_INTERVAL, Timer::Gossip);
+        Ok(CounterNode {
+            counter: Replicator::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload<C>, Timer>,
+        ctx: &mut Ctx<Self, Timer>,
+    ) -> eyre::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Timer(Timer::Gossip) => {
+                // Unacknowledged changes go out again every round, so whatever
+                // got lost or cut off by a partition is made up for as soon as
+                // one gets through.
+                return self
+                    .counter
+                    .gossip(ctx, |node: &mut Self| &mut node.counter, Payload::Gossip)
+                    .context("gossip counter");
+            }
+            Event::Eof => return Ok(()),
+        };
+
+        match &input.body.payload {
+            Payload::Add { delta } => {
+                if !self.counter.caught_up(ctx.peers()) {
+                    let error = Error::new(
+                        ErrorCode::TemporarilyUnavailable,
+                        "waiting for the peers' counts",
+                    );
+                    return Err(error.into());
+                }
+                let node = ctx.node_id();
+                let delta = self.counter.state().add_delta(node, *delta)?;
+                self.counter.apply(delta);
+                ctx.reply(&input, Payload::<C>::AddOk)
+                    .context("reply to add")?;
+            }
+            Payload::Read => {
+                let value = self.counter.state().value();
+                ctx.reply(&input, Payload::<C>::ReadOk { value })
+                    .context("reply to read")?;
+            }
+            Payload::Gossip(delta) => {
+                let ack = self.counter.receive(&input.src, delta);
+                ctx.reply(&input, Payload::<C>::GossipOk(ack))
+                    .context("reply to gossip")?;
+            }
+            Payload::AddOk | Payload::ReadOk { .. } | Payload::GossipOk(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    match std::env::var("CRDT_COUNTER").as_deref() {
+        Ok("g") => main_loop::<_, CounterNode<GCounter>, _, _>(()),
+        Ok("pn") | Err(_) => main_loop::<_, CounterNode<PnCounter>, _, _>(()),
+        Ok(other) => bail!("unknown CRDT_COUNTER {other}, expected g or pn"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vortice::{
+        sim::{Fault, Sim, SimConfig},
+        Msg, RpcError,
+    };
+
+    use super::*;
+
+    type CounterSim = Sim<(), CounterNode<GCounter>, Payload<GCounter>, Timer>;
+
+    /// Adds `delta` through `node`, trying again while it is unavailable;
+    /// returns how many times it was turned away.
+    fn add(sim: &mut CounterSim, node: &str, delta: i64) -> usize {
+        let mut refusals = 0;
+        loop {
+            let timeout = Duration::from_secs(1);
+            match sim.call::<_, Payload<GCounter>>(
+                "c1",
+                node,
+                Payload::<GCounter>::Add { delta },
+                timeout,
+            ) {
+                Ok(_) => return refusals,
+                Err(e) => match e.downcast_ref::<RpcError>() {
+                    Some(RpcError::Maelstrom(error))
+                        if error.code == ErrorCode::TemporarilyUnavailable =>
+                    {
+                        refusals += 1;
+                        sim.run_for(Duration::from_millis(100)).unwrap();
+                    }
+                    _ => panic!("add failed: {e:#}"),
+                },
+            }
+        }
+    }
+
+    fn read(sim: &mut CounterSim, node: &str) -> i64 {
+        let reply: Msg<Payload<GCounter>> = sim
+            .call(
+                "c1",
+                node,
+                Payload::<GCounter>::Read,
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        match reply.body.payload {
+            Payload::ReadOk { value } => value,
+            payload => panic!("read got {payload:?}"),
+        }
+    }
+
+    #[test]
+    fn adds_wait_for_the_peers_after_losing_state() {
+        let mut sim = CounterSim::new(SimConfig::default(), 3, ()).unwrap();
+        add(&mut sim, "n1", 5);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+
+        sim.apply(Fault::Crash("n1".to_owned())).unwrap();
+        sim.apply(Fault::Restart {
+            node: "n1".to_owned(),
+            lose_state: true,
+        })
+        .unwrap();
+        assert!(add(&mut sim, "n1", 1) > 0, "took an add before catching up");
+        sim.run_for(Duration::from_secs(1)).unwrap();
+
+        for node in ["n0", "n1", "n2"] {
+            assert_eq!(read(&mut sim, node), 6, "{node}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use vortice::{main_loop, Ctx, Event, Init, Node};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+struct EchoNode;
+
+impl Node<(), Payload> for EchoNode {
+    fn from_init(_state: (), _init: Init, _ctx: &mut Ctx<Self>) -> eyre::Result<Self> {
+        Ok(EchoNode)
+    }
+
+    fn step(&mut self, input: Event<Payload>, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+
+        match &input.body.payload {
+            Payload::Echo { echo } => {
+                let echo = echo.clone();
+                ctx.reply(&input, Payload::EchoOk { echo })
+                    .context("reply to echo")?;
+            }
+            Payload::EchoOk { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    main_loop::<_, EchoNode, _, _>(())
+}
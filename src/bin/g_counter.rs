@@ -0,0 +1,411 @@
+//! A grow-only counter kept in Maelstrom's `seq-kv` service.
+//!
+//! Every node owns one key, named after itself, holding everything added
+//! through it; a read sums all of them. Since nobody else writes a node's
+//! key, its writes never contend, and a compare-and-set from the last value
+//! it saw is enough to notice when it lost track.
+//!
+//! A save that times out may still land any time later, so it is sent again
+//! as is until the service answers it: `cas_ok` if it had not landed yet,
+//! `precondition-failed` if it had, since nothing else moves our key off
+//! `from`. Only then does the next save go out.
+//!
+//! `seq-kv` may serve reads from any state at least as new as the client's
+//! own last operation, so before summing a node writes a fresh value to a
+//! scratch key; that pins its view to the present.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use vortice::{
+    kv::{Kv, KvError},
+    main_loop, Ctx, Error, ErrorCode, Event, Init, Msg, Node,
+};
+
+const KV_TIMEOUT: Duration = Duration::from_millis(500);
+/// Written before every read, see the module docs.
+const SYNC_KEY: &str = "sync";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add { delta: u64 },
+    AddOk,
+    Read,
+    ReadOk { value: u64 },
+}
+
+/// An add waiting to be saved, and by how much.
+type Add = (Msg<()>, u64);
+
+/// A compare-and-set of our key, and the adds it acknowledges.
+struct Save {
+    from: u64,
+    to: u64,
+    adds: Vec<Add>,
+}
+
+/// A client read waiting for every node's key.
+struct Sum {
+    request: Msg<()>,
+    missing: BTreeSet<String>,
+    total: u64,
+}
+
+struct CounterNode {
+    kv: Kv,
+    /// What our key holds as far as we know. `None` until it has been read,
+    /// and again after a save found something else there.
+    stored: Option<u64>,
+    /// Adds since `stored`, to acknowledge once saved.
+    waiting: Vec<Add>,
+    /// A save that failed without saying whether it happened, to send again
+    /// until it gets an answer.
+    uncertain: Option<Save>,
+    /// Whether a read or compare-and-set of our key is outstanding; there is
+    /// never more than one.
+    busy: bool,
+    sums: BTreeMap<usize, Sum>,
+    next_sum: usize,
+}
+
+/// A counter, or 0 for a key that was never written.
+fn counter(value: Result<u64, KvError>) -> Result<u64, KvError> {
+    match value {
+        Err(KvError::KeyDoesNotExist) => Ok(0),
+        value => value,
+    }
+}
+
+impl CounterNode {
+    /// Writes whatever was added since the last save, first finding out what
+    /// our key holds if we do not know, and settling an uncertain save.
+    fn save(&mut self, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        if self.busy {
+            return Ok(());
+        }
+        if let Some(save) = self.uncertain.take() {
+            return self.cas(save, true, ctx);
+        }
+        let Some(from) = self.stored else {
+            return self.load(ctx);
+        };
+        // Waiting adds, not their total: an add of 0 changes nothing but
+        // still needs a save behind it before it can be acknowledged.
+        if self.waiting.is_empty() {
+            return Ok(());
+        }
+
+        let mut save = Save {
+            from,
+            to: from,
+            adds: Vec::new(),
+        };
+        for (add, delta) in std::mem::take(&mut self.waiting) {
+            match save.to.checked_add(delta) {
+                Some(to) => {
+                    save.to = to;
+                    save.adds.push((add, delta));
+                }
+                None => {
+                    let error = Error::new(
+                        ErrorCode::Abort,
+                        format!("adding {delta} to {} overflows the counter", save.to),
+                    );
+                    ctx.reply_error(&add, error).context("reply to add")?;
+                }
+            }
+        }
+        if save.adds.is_empty() {
+            return Ok(());
+        }
+        self.cas(save, false, ctx)
+    }
+
+    /// Sends `save`, or sends it again if it is `uncertain`.
+    fn cas(&mut self, save: Save, again: bool, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        self.busy = true;
+        let (key, from, to) = (ctx.node_id().to_owned(), save.from, save.to);
+        self.kv
+            .cas(
+                ctx,
+                key,
+                from,
+                to,
+                true,
+                move |node: &mut Self, saved: Result<(), KvError>, ctx| {
+                    node.busy = false;
+                    match saved {
+                        Ok(()) => node.saved(save, ctx)?,
+                        // An earlier try landed: nothing else moves our key.
+                        Err(KvError::PreconditionFailed) if again => node.saved(save, ctx)?,
+                        Err(e) if again || !e.code().is_definite() => {
+                            eprintln!("{}: saving {} is uncertain: {e}", ctx.node_id(), save.to);
+                            node.uncertain = Some(save);
+                        }
+                        Err(e) => {
+                            eprintln!("{}: saving {} failed: {e}", ctx.node_id(), save.to);
+                            node.stored = None;
+                            node.retry(save);
+                        }
+                    }
+                    node.save(ctx)
+                },
+            )
+            .context("cas counter")?;
+
+        Ok(())
+    }
+
+    /// Reads our key into `stored`.
+    fn load(&mut self, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        self.busy = true;
+        let key = ctx.node_id().to_owned();
+        self.kv
+            .read(
+                ctx,
+                key,
+                |node: &mut Self, stored: Result<u64, KvError>, ctx| {
+                    node.busy = false;
+                    match counter(stored) {
+                        Ok(stored) => node.stored = Some(stored),
+                        Err(e) => {
+                            eprintln!("{}: reading own counter failed: {e}", ctx.node_id());
+                        }
+                    }
+                    node.save(ctx)
+                },
+            )
+            .context("read counter")?;
+
+        Ok(())
+    }
+
+    fn saved(&mut self, save: Save, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        self.stored = Some(save.to);
+        for (add, _) in &save.adds {
+            ctx.reply(add, Payload::AddOk).context("reply to add")?;
+        }
+        Ok(())
+    }
+
+    /// Puts a save that did not happen back in line.
+    fn retry(&mut self, save: Save) {
+        let mut adds = save.adds;
+        adds.append(&mut self.waiting);
+        self.waiting = adds;
+    }
+
+    /// Catches up with the present, then reads every node's key.
+    fn sync(&mut self, id: usize, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        let value = format!("{}-{id}", ctx.node_id());
+        self.kv
+            .write(
+                ctx,
+                SYNC_KEY,
+                value,
+                move |node: &mut Self, written: Result<(), KvError>, ctx| match written {
+                    Ok(()) => {
+                        let keys = node
+                            .sums
+                            .get(&id)
+                            .map(|sum| sum.missing.clone())
+                            .unwrap_or_default();
+                        for key in keys {
+                            node.fetch(id, key, ctx)?;
+                        }
+                        Ok(())
+                    }
+                    Err(e) => {
+                        eprintln!("{}: sync write failed: {e}", ctx.node_id());
+                        node.sync(id, ctx)
+                    }
+                },
+            )
+            .context("write sync key")?;
+
+        Ok(())
+    }
+
+    fn fetch(&mut self, id: usize, key: String, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        self.kv
+            .read(
+                ctx,
+                key.clone(),
+                move |node: &mut Self, count: Result<u64, KvError>, ctx| {
+                    let count = match counter(count) {
+                        Ok(count) => count,
+                        Err(e) => {
+                            eprintln!("{}: reading {key} failed: {e}", ctx.node_id());
+                            return node.fetch(id, key, ctx);
+                        }
+                    };
+
+                    let Some(sum) = node.sums.get_mut(&id) else {
+                        return Ok(());
+                    };
+                    if sum.missing.remove(&key) {
+                        sum.total += count;
+                    }
+                    if sum.missing.is_empty() {
+                        let sum = node.sums.remove(&id).expect("just looked it up");
+                        ctx.reply(&sum.request, Payload::ReadOk { value: sum.total })
+                            .context("reply to read")?;
+                    }
+                    Ok(())
+                },
+            )
+            .context("read counter")?;
+
+        Ok(())
+    }
+}
+
+impl Node<(), Payload> for CounterNode {
+    fn from_init(_state: (), _init: Init, ctx: &mut Ctx<Self>) -> eyre::Result<Self> {
+        let mut node = CounterNode {
+            kv: Kv::seq().with_timeout(KV_TIMEOUT),
+            stored: None,
+            waiting: Vec::new(),
+            uncertain: None,
+            busy: false,
+            sums: BTreeMap::new(),
+            next_sum: 0,
+        };
+        node.load(ctx)?;
+        Ok(node)
+    }
+
+    fn step(&mut self, input: Event<Payload>, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+
+        match &input.body.payload {
+            Payload::Add { delta } => {
+                self.waiting.push((input.header(), *delta));
+                self.save(ctx)?;
+            }
+            Payload::Read => {
+                let id = self.next_sum;
+                self.next_sum += 1;
+                self.sums.insert(
+                    id,
+                    Sum {
+                        request: input.header(),
+                        missing: ctx.node_ids().iter().cloned().collect(),
+                        total: 0,
+                    },
+                );
+                self.sync(id, ctx)?;
+            }
+            Payload::AddOk | Payload::ReadOk { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    main_loop::<_, CounterNode, _, _>(())
+}
+
+#[cfg(test)]
+mod tests {
+    use vortice::{
+        kv::Service,
+        sim::{Sim, SimConfig},
+    };
+
+    use super::*;
+
+    #[test]
+    fn late_saves_are_counted_once() {
+        for seed in 0..20 {
+            late_saves(seed);
+        }
+    }
+
+    fn late_saves(seed: u64) {
+        // Messages to seq-kv take up to a second against a 500ms timeout, so
+        // many saves time out and only land after our next look at the key.
+        let config = SimConfig {
+            seed,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(1000),
+            loss: 0.0,
+        };
+        let mut sim: Sim<(), CounterNode, Payload> = Sim::new(config, 3, ()).unwrap();
+        sim.add_service(Service::seq(seed));
+
+        let mut adds = Vec::new();
+        for delta in 1..=150 {
+            let node = format!("n{}", delta % 3);
+            adds.push((
+                sim.request("c1", &node, Payload::Add { delta }).unwrap(),
+                delta,
+            ));
+            sim.run_for(Duration::from_millis(50)).unwrap();
+        }
+        sim.run_for(Duration::from_secs(60)).unwrap();
+
+        let acked: u64 = adds
+            .iter()
+            .filter(|(id, _)| {
+                sim.reply("c1", *id)
+                    .is_some_and(|reply| reply.body.payload["type"] == "add_ok")
+            })
+            .map(|(_, delta)| delta)
+            .sum();
+        assert_eq!(
+            acked,
+            (1..=150).sum::<u64>(),
+            "seed {seed}: every add went through eventually"
+        );
+        let uncertain: usize = sim
+            .node_ids()
+            .filter(|id| sim.node(id).unwrap().uncertain.is_some())
+            .count();
+        assert_eq!(uncertain, 0);
+
+        for node in ["n0", "n1", "n2"] {
+            let read: Msg<Payload> = sim
+                .call("c1", node, Payload::Read, Duration::from_secs(60))
+                .unwrap();
+            let Payload::ReadOk { value } = read.body.payload else {
+                panic!("{node} answered a read with {:?}", read.body.payload);
+            };
+            assert_eq!(value, acked, "seed {seed}: {node}");
+        }
+    }
+
+    #[test]
+    fn overflowing_adds_fail() {
+        let mut sim: Sim<(), CounterNode, Payload> = Sim::new(SimConfig::default(), 1, ()).unwrap();
+        sim.add_service(Service::seq(0));
+        let timeout = Duration::from_secs(5);
+
+        let big = u64::MAX - 1;
+        let reply: Msg<Payload> = sim
+            .call("c1", "n0", Payload::Add { delta: big }, timeout)
+            .unwrap();
+        assert!(matches!(reply.body.payload, Payload::AddOk));
+        let overflow = sim.call::<_, Payload>("c1", "n0", Payload::Add { delta: 2 }, timeout);
+        assert!(overflow.is_err(), "{overflow:?}");
+        let reply: Msg<Payload> = sim
+            .call("c1", "n0", Payload::Add { delta: 1 }, timeout)
+            .unwrap();
+        assert!(matches!(reply.body.payload, Payload::AddOk));
+
+        let read: Msg<Payload> = sim.call("c1", "n0", Payload::Read, timeout).unwrap();
+        assert!(matches!(
+            read.body.payload,
+            Payload::ReadOk { value: u64::MAX }
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+//! A Kafka-style log on a single node: every key has its own append-only log
+//! of messages, numbered by offset from 0, and consumers poll from an offset
+//! and commit how far they got.
+//!
+//! Nothing is shared between nodes, so this is for the single-node workload
+//! (`--node-count 1`).
+
+use std::collections::BTreeMap;
+
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use vortice::{main_loop, Ctx, Event, Init, Node};
+
+/// The most messages a poll returns per key; consumers poll again from past
+/// the last one for the rest.
+const POLL_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Send {
+        key: String,
+        msg: Value,
+    },
+    SendOk {
+        offset: u64,
+    },
+    Poll {
+        offsets: BTreeMap<String, u64>,
+    },
+    /// For each key, `[offset, msg]` pairs in ascending order.
+    PollOk {
+        msgs: BTreeMap<String, Vec<(u64, Value)>>,
+    },
+    CommitOffsets {
+        offsets: BTreeMap<String, u64>,
+    },
+    CommitOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: BTreeMap<String, u64>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Log {
+    /// The message at each offset.
+    msgs: Vec<Value>,
+    /// The highest offset consumers committed, if any.
+    committed: Option<u64>,
+}
+
+impl Log {
+    fn append(&mut self, msg: Value) -> u64 {
+        self.msgs.push(msg);
+        self.msgs.len() as u64 - 1
+    }
+
+    /// Up to [`POLL_LIMIT`] messages from `offset` on.
+    fn read_from(&self, offset: u64) -> Vec<(u64, Value)> {
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        self.msgs
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(POLL_LIMIT)
+            .map(|(offset, msg)| (offset as u64, msg.clone()))
+            .collect()
+    }
+
+    /// Commits never go backwards: a consumer that commits an older offset
+    /// is behind, not rewinding.
+    fn commit(&mut self, offset: u64) {
+        self.committed = Some(self.committed.map_or(offset, |c| c.max(offset)));
+    }
+}
+
+struct KafkaNode {
+    logs: BTreeMap<String, Log>,
+}
+
+impl Node<(), Payload> for KafkaNode {
+    fn from_init(_state: (), _init: Init, _ctx: &mut Ctx<Self>) -> eyre::Result<Self> {
+        Ok(KafkaNode {
+            logs: BTreeMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+
+        match &input.body.payload {
+            Payload::Send { key, msg } => {
+                let offset = self
+                    .logs
+                    .entry(key.clone())
+                    .or_default()
+                    .append(msg.clone());
+                ctx.reply(&input, Payload::SendOk { offset })
+                    .context("reply to send")?;
+            }
+            Payload::Poll { offsets } => {
+                let msgs = offsets
+                    .iter()
+                    .filter_map(|(key, offset)| {
+                        let msgs = self.logs.get(key)?.read_from(*offset);
+                        (!msgs.is_empty()).then(|| (key.clone(), msgs))
+                    })
+                    .collect();
+                ctx.reply(&input, Payload::PollOk { msgs })
+                    .context("reply to poll")?;
+            }
+            Payload::CommitOffsets { offsets } => {
+                for (key, offset) in offsets {
+                    self.logs.entry(key.clone()).or_default().commit(*offset);
+                }
+                ctx.reply(&input, Payload::CommitOffsetsOk)
+                    .context("reply to commit_offsets")?;
+            }
+            Payload::ListCommittedOffsets { keys } => {
+                let offsets = keys
+                    .iter()
+                    .filter_map(|key| Some((key.clone(), self.logs.get(key)?.committed?)))
+                    .collect();
+                ctx.reply(&input, Payload::ListCommittedOffsetsOk { offsets })
+                    .context("reply to list_committed_offsets")?;
+            }
+            Payload::SendOk { .. }
+            | Payload::PollOk { .. }
+            | Payload::CommitOffsetsOk
+            | Payload::ListCommittedOffsetsOk { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    main_loop::<_, KafkaNode, _, _>(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use vortice::{
+        sim::{Sim, SimConfig},
+        Msg,
+    };
+
+    use super::*;
+
+    fn node() -> Sim<(), KafkaNode, Payload> {
+        Sim::new(SimConfig::default(), 1, ()).unwrap()
+    }
+
+    fn call(sim: &mut Sim<(), KafkaNode, Payload>, request: Payload) -> Payload {
+        let reply: Msg<Payload> = sim
+            .call("c1", "n0", request, Duration::from_secs(1))
+            .unwrap();
+        reply.body.payload
+    }
+
+    fn send(sim: &mut Sim<(), KafkaNode, Payload>, key: &str, msg: Value) -> u64 {
+        let send = Payload::Send {
+            key: key.to_owned(),
+            msg,
+        };
+        match call(sim, send) {
+            Payload::SendOk { offset } => offset,
+            reply => panic!("send got {reply:?}"),
+        }
+    }
+
+    fn poll(
+        sim: &mut Sim<(), KafkaNode, Payload>,
+        offsets: &[(&str, u64)],
+    ) -> BTreeMap<String, Vec<(u64, Value)>> {
+        let offsets = offsets.iter().map(|(k, o)| (k.to_string(), *o)).collect();
+        match call(sim, Payload::Poll { offsets }) {
+            Payload::PollOk { msgs } => msgs,
+            reply => panic!("poll got {reply:?}"),
+        }
+    }
+
+    fn committed(sim: &mut Sim<(), KafkaNode, Payload>, keys: &[&str]) -> Vec<(String, u64)> {
+        let keys = keys.iter().map(|k| k.to_string()).collect();
+        match call(sim, Payload::ListCommittedOffsets { keys }) {
+            Payload::ListCommittedOffsetsOk { offsets } => offsets.into_iter().collect(),
+            reply => panic!("list_committed_offsets got {reply:?}"),
+        }
+    }
+
+    #[test]
+    fn every_key_counts_its_own_offsets() {
+        let mut sim = node();
+        let offsets: Vec<u64> = ["a", "b", "a", "a", "b"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, key)| send(&mut sim, key, json!(i)))
+            .collect();
+        assert_eq!(offsets, [0, 0, 1, 2, 1]);
+
+        let msgs = poll(&mut sim, &[("a", 1), ("b", 0), ("c", 0)]);
+        let expected = BTreeMap::from([
+            ("a".to_owned(), vec![(1, json!(2)), (2, json!(3))]),
+            ("b".to_owned(), vec![(0, json!(1)), (1, json!(4))]),
+        ]);
+        assert_eq!(msgs, expected);
+    }
+
+    #[test]
+    fn polls_return_at_most_the_limit() {
+        let mut sim = node();
+        let total = 2 * POLL_LIMIT + 10;
+        for i in 0..total {
+            send(&mut sim, "a", json!(i));
+        }
+
+        let mut offset = 0;
+        let mut batches = Vec::new();
+        loop {
+            let mut msgs = poll(&mut sim, &[("a", offset)]);
+            let Some(batch) = msgs.remove("a") else {
+                break;
+            };
+            assert!(batch
+                .iter()
+                .zip(offset..)
+                .all(|((o, msg), i)| *o == i && *msg == json!(i)));
+            offset += batch.len() as u64;
+            batches.push(batch.len());
+        }
+        assert_eq!(batches, [POLL_LIMIT, POLL_LIMIT, 10]);
+        assert_eq!(offset, total as u64);
+    }
+
+    #[test]
+    fn commits_never_go_backwards() {
+        let mut sim = node();
+        let commit = |offsets: &[(&str, u64)]| Payload::CommitOffsets {
+            offsets: offsets.iter().map(|(k, o)| (k.to_string(), *o)).collect(),
+        };
+        assert!(matches!(
+            call(&mut sim, commit(&[("a", 5), ("b", 3)])),
+            Payload::CommitOffsetsOk
+        ));
+        assert!(matches!(
+            call(&mut sim, commit(&[("a", 2), ("b", 4)])),
+            Payload::CommitOffsetsOk
+        ));
+
+        // Keys nobody committed are left out.
+        assert_eq!(
+            committed(&mut sim, &["a", "b", "c"]),
+            [("a".to_owned(), 5), ("b".to_owned(), 4)]
+        );
+        assert_eq!(committed(&mut sim, &["b"]), [("b".to_owned(), 4)]);
+    }
+}
@@ -0,0 +1,40 @@
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+use vortice::{main_loop, Ctx, Event, Init, Node};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Generate,
+    GenerateOk { id: String },
+}
+
+struct UniqueNode;
+
+impl Node<(), Payload> for UniqueNode {
+    fn from_init(_state: (), _init: Init, _ctx: &mut Ctx<Self>) -> eyre::Result<Self> {
+        Ok(UniqueNode)
+    }
+
+    fn step(&mut self, input: Event<Payload>, ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+
+        match &input.body.payload {
+            Payload::Generate => {
+                let id = ulid::Ulid::new().to_string();
+                ctx.reply(&input, Payload::GenerateOk { id })
+                    .context("reply to generate")?;
+            }
+            Payload::GenerateOk { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    main_loop::<_, UniqueNode, _, _>(())
+}
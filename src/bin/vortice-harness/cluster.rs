@@ -0,0 +1,115 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+
+use eyre::{eyre, Context};
+use serde_json::Value;
+use vortice::Msg;
+
+/// A line one of the node processes wrote to stdout.
+pub struct Output {
+    pub node: String,
+    pub line: String,
+}
+
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+/// N copies of a node binary, with their stdout funnelled into one channel.
+pub struct Cluster {
+    processes: BTreeMap<String, Process>,
+    rx: Receiver<Output>,
+}
+
+impl Cluster {
+    pub fn spawn(bin: &Path, node_ids: &[String]) -> eyre::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let mut processes = BTreeMap::new();
+
+        for id in node_ids {
+            let mut child = Command::new(bin)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::inherit())
+                .spawn()
+                .with_context(|| format!("spawn {} as {id}", bin.display()))?;
+
+            let stdout = child.stdout.take().expect("stdout is piped");
+            let tx = tx.clone();
+            let node = id.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    let output = Output {
+                        node: node.clone(),
+                        line,
+                    };
+                    if tx.send(output).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let stdin = child.stdin.take();
+            processes.insert(id.clone(), Process { child, stdin });
+        }
+
+        Ok(Self { processes, rx })
+    }
+
+    pub fn is_node(&self, id: &str) -> bool {
+        self.processes.contains_key(id)
+    }
+
+    pub fn send(&mut self, msg: &Msg<Value>) -> eyre::Result<()> {
+        let process = self
+            .processes
+            .get_mut(&msg.dst)
+            .ok_or_else(|| eyre!("no node {}", msg.dst))?;
+        let Some(stdin) = process.stdin.as_mut() else {
+            return Ok(());
+        };
+        msg.send(stdin)
+            .and_then(|()| stdin.flush().context("Write::flush failed"))
+            .with_context(|| format!("write to {}", msg.dst))
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Output> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    /// Closes every node's stdin and waits for it to exit, killing the ones
+    /// that do not within `grace`.
+    pub fn shutdown(mut self, grace: Duration) -> eyre::Result<()> {
+        for process in self.processes.values_mut() {
+            process.stdin.take();
+        }
+
+        let deadline = std::time::Instant::now() + grace;
+        for (id, process) in &mut self.processes {
+            loop {
+                if process.child.try_wait()?.is_some() {
+                    break;
+                }
+                if std::time::Instant::now() >= deadline {
+                    eprintln!("{id} did not exit after stdin was closed, killing it");
+                    process.child.kill().ok();
+                    process.child.wait().ok();
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! A local stand-in for running `maelstrom test`: spawns the node binary once
+//! per node, routes their messages between each other, a client and
+//! in-process `seq-kv`, `lin-kv` and `lww-kv` services, and checks the
+//! client's view of the run.
+//!
+//! ```text
+//! vortice-harness -w broadcast --bin target/release/broadcast \
+//!     --node-count 5 --time-limit 10 --rate 10
+//! ```
+
+mod cluster;
+mod workload;
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use eyre::{bail, eyre, Context};
+use serde_json::Value;
+use vortice::{kv::Service, Body, Error, ErrorCode, Init, InitPayload, Msg, Rng};
+
+use cluster::Cluster;
+use workload::Workload;
+
+const CLIENT: &str = "c1";
+
+struct Args {
+    workload: String,
+    bin: PathBuf,
+    node_count: usize,
+    time_limit: Duration,
+    rate: f64,
+    /// How long to let the cluster settle before the final requests.
+    recovery: Duration,
+    timeout: Duration,
+    seed: u64,
+}
+
+impl Args {
+    fn parse() -> eyre::Result<Self> {
+        let mut workload = None;
+        let mut bin = None;
+        let mut args = Args {
+            workload: String::new(),
+            bin: PathBuf::new(),
+            node_count: 1,
+            time_limit: Duration::from_secs(5),
+            rate: 10.0,
+            recovery: Duration::from_secs(2),
+            timeout: Duration::from_secs(1),
+            seed: 0,
+        };
+
+        let mut argv = std::env::args().skip(1);
+        while let Some(flag) = argv.next() {
+            let mut value = || argv.next().ok_or_else(|| eyre!("{flag} needs a value"));
+            match flag.as_str() {
+                "-w" | "--workload" => workload = Some(value()?),
+                "--bin" => bin = Some(PathBuf::from(value()?)),
+                "--node-count" => args.node_count = value()?.parse().context("--node-count")?,
+                "--time-limit" => args.time_limit = secs(&value()?).context("--time-limit")?,
+                "--rate" => args.rate = value()?.parse().context("--rate")?,
+                "--recovery" => args.recovery = secs(&value()?).context("--recovery")?,
+                "--timeout" => args.timeout = secs(&value()?).context("--timeout")?,
+                "--seed" => args.seed = value()?.parse().context("--seed")?,
+                other => bail!("unknown argument {other}"),
+            }
+        }
+
+        args.workload = workload.ok_or_else(|| eyre!("missing -w <workload>"))?;
+        args.bin = bin.ok_or_else(|| eyre!("missing --bin <path>"))?;
+        if args.node_count == 0 || !args.rate.is_finite() || args.rate <= 0.0 {
+            bail!("--node-count and --rate must be positive");
+        }
+        Ok(args)
+    }
+}
+
+fn secs(value: &str) -> eyre::Result<Duration> {
+    let secs: f64 = value.parse()?;
+    Duration::try_from_secs_f64(secs).map_err(|_| eyre!("{value} is not a valid number of seconds"))
+}
+
+#[derive(Default)]
+struct Stats {
+    client_msgs: usize,
+    server_msgs: usize,
+    service_msgs: usize,
+    ops: usize,
+    ok: usize,
+    failed: usize,
+    timed_out: usize,
+    latencies: Vec<Duration>,
+}
+
+impl Stats {
+    /// Workloads only check the replies they got, so a cluster that answers
+    /// nothing would otherwise pass.
+    fn check(&self) -> eyre::Result<()> {
+        if self.ops > 0 && self.ok == 0 {
+            bail!("none of the {} ops succeeded", self.ops);
+        }
+        Ok(())
+    }
+
+    fn report(&mut self) {
+        self.latencies.sort();
+        let percentile = |p: f64| {
+            let i = ((self.latencies.len() as f64 - 1.0) * p).round() as usize;
+            self.latencies.get(i).copied().unwrap_or_default()
+        };
+
+        println!(
+            "ops:      {} ({} ok, {} failed, {} timed out)",
+            self.ops, self.ok, self.failed, self.timed_out
+        );
+        println!(
+            "messages: {} client, {} inter-server ({:.2} per op), {} service",
+            self.client_msgs,
+            self.server_msgs,
+            self.server_msgs as f64 / self.ops.max(1) as f64,
+            self.service_msgs
+        );
+        println!(
+            "latency:  median {:?}, p99 {:?}, max {:?}",
+            percentile(0.5),
+            percentile(0.99),
+            percentile(1.0)
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Init,
+    /// Made for the workload but not part of the measured run, like setting
+    /// the topology or the final reads.
+    Setup,
+    Op,
+}
+
+struct Pending {
+    node: String,
+    request: Value,
+    sent_at: Instant,
+    kind: Kind,
+}
+
+struct Harness {
+    cluster: Cluster,
+    services: BTreeMap<String, Service>,
+    workload: Box<dyn Workload>,
+    timeout: Duration,
+    next_msg_id: usize,
+    pending: BTreeMap<usize, Pending>,
+    /// Replies to everything but ops, for [`Harness::settle`].
+    replies: BTreeMap<usize, Value>,
+    stats: Stats,
+}
+
+impl Harness {
+    fn request(&mut self, node: &str, payload: Value, kind: Kind) -> eyre::Result<usize> {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+
+        let msg = Msg {
+            src: CLIENT.to_owned(),
+            dst: node.to_owned(),
+            body: Body {
+                id: Some(msg_id),
+                in_reply_to: None,
+                payload: payload.clone(),
+            },
+        };
+        self.cluster.send(&msg)?;
+        self.stats.client_msgs += 1;
+        if kind == Kind::Op {
+            self.stats.ops += 1;
+        }
+
+        self.pending.insert(
+            msg_id,
+            Pending {
+                node: node.to_owned(),
+                request: payload,
+                sent_at: Instant::now(),
+                kind,
+            },
+        );
+        Ok(msg_id)
+    }
+
+    /// Routes messages until `until`.
+    fn pump(&mut self, until: Instant) -> eyre::Result<()> {
+        loop {
+            self.expire();
+            let now = Instant::now();
+            if now >= until {
+                return Ok(());
+            }
+            let Some(output) = self.cluster.recv_timeout(until - now) else {
+                continue;
+            };
+
+            let msg: Msg<Value> = match serde_json::from_str(&output.line) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("{} wrote something that is not a message: {e}", output.node);
+                    continue;
+                }
+            };
+            if msg.src != output.node {
+                eprintln!("{} sent a message as {}", output.node, msg.src);
+            }
+            self.route(msg)?;
+        }
+    }
+
+    /// Sends requests and routes messages until they are all answered or time
+    /// out, returning the replies.
+    fn settle(
+        &mut self,
+        requests: Vec<(String, Value)>,
+        kind: Kind,
+    ) -> eyre::Result<Vec<Option<Value>>> {
+        let mut ids = Vec::new();
+        for (node, payload) in requests {
+            ids.push(self.request(&node, payload, kind)?);
+        }
+
+        let deadline = Instant::now() + self.timeout;
+        while ids.iter().any(|id| self.pending.contains_key(id)) && Instant::now() < deadline {
+            self.pump((Instant::now() + Duration::from_millis(10)).min(deadline))?;
+        }
+        self.expire_all();
+
+        Ok(ids.iter().map(|id| self.replies.remove(id)).collect())
+    }
+
+    fn route(&mut self, msg: Msg<Value>) -> eyre::Result<()> {
+        if self.cluster.is_node(&msg.dst) {
+            self.stats.server_msgs += 1;
+            return self.cluster.send(&msg);
+        }
+
+        if let Some(service) = self.services.get_mut(&msg.dst) {
+            self.stats.service_msgs += 1;
+            if let Some(reply) = service.handle(msg, Instant::now()) {
+                self.stats.service_msgs += 1;
+                self.cluster.send(&reply)?;
+            }
+            return Ok(());
+        }
+
+        if msg.dst != CLIENT {
+            eprintln!("{} sent a message to unknown node {}", msg.src, msg.dst);
+            if msg.body.id.is_some() && msg.body.in_reply_to.is_none() {
+                let error = Error::new(ErrorCode::NodeNotFound, format!("no node {}", msg.dst));
+                self.cluster.send(&Msg {
+                    src: msg.dst,
+                    dst: msg.src,
+                    body: Body {
+                        id: None,
+                        in_reply_to: msg.body.id,
+                        payload: serde_json::to_value(error)?,
+                    },
+                })?;
+            }
+            return Ok(());
+        }
+
+        self.stats.client_msgs += 1;
+        let Some(pending) = msg.body.in_reply_to.and_then(|id| self.pending.remove(&id)) else {
+            eprintln!("{} sent an unsolicited message to the client", msg.src);
+            return Ok(());
+        };
+
+        let reply = msg.body.payload;
+        if pending.kind == Kind::Op {
+            self.stats.latencies.push(pending.sent_at.elapsed());
+            if Error::is_error_payload(&reply) {
+                self.stats.failed += 1;
+            } else {
+                self.stats.ok += 1;
+            }
+        }
+        if pending.kind != Kind::Init {
+            self.workload
+                .complete(&pending.node, &pending.request, Some(&reply));
+        }
+        if pending.kind != Kind::Op {
+            self.replies
+                .insert(msg.body.in_reply_to.expect("matched a request"), reply);
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self) {
+        let timeout = self.timeout;
+        let expired: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.sent_at.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            self.time_out(id);
+        }
+    }
+
+    fn expire_all(&mut self) {
+        let ids: Vec<usize> = self.pending.keys().copied().collect();
+        for id in ids {
+            self.time_out(id);
+        }
+    }
+
+    fn time_out(&mut self, id: usize) {
+        let Some(pending) = self.pending.remove(&id) else {
+            return;
+        };
+        if pending.kind == Kind::Op {
+            self.stats.timed_out += 1;
+        }
+        if pending.kind != Kind::Init {
+            self.workload
+                .complete(&pending.node, &pending.request, None);
+        }
+    }
+}
+
+fn main() -> eyre::Result<()> {
+    let args = Args::parse()?;
+    let workload = workload::by_name(&args.workload)
+        .ok_or_else(|| eyre!("unknown workload {}", args.workload))?;
+
+    let node_ids: Vec<String> = (0..args.node_count).map(|i| format!("n{i}")).collect();
+    let cluster = Cluster::spawn(&args.bin, &node_ids)?;
+    let services = [
+        Service::seq(args.seed),
+        Service::lin(),
+        Service::lww(args.seed),
+    ];
+    let mut harness = Harness {
+        cluster,
+        services: services
+            .into_iter()
+            .map(|s| (s.name().to_owned(), s))
+            .collect(),
+        workload,
+        timeout: args.timeout,
+        next_msg_id: 0,
+        pending: BTreeMap::new(),
+        replies: BTreeMap::new(),
+        stats: Stats::default(),
+    };
+
+    let result = run(&mut harness, &args, &node_ids);
+    harness.stats.report();
+
+    let verdict = result
+        .and_then(|()| harness.stats.check())
+        .and_then(|()| harness.workload.check().map_err(|e| eyre!(e)));
+    harness.cluster.shutdown(Duration::from_secs(1))?;
+
+    match verdict {
+        Ok(()) => {
+            println!("result:   PASS");
+            Ok(())
+        }
+        Err(e) => {
+            println!("result:   FAIL: {e:#}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(harness: &mut Harness, args: &Args, node_ids: &[String]) -> eyre::Result<()> {
+    let inits = node_ids
+        .iter()
+        .map(|id| {
+            let init = InitPayload::Init(Init {
+                node_id: id.clone(),
+                node_ids: node_ids.to_vec(),
+            });
+            Ok((id.clone(), serde_json::to_value(init)?))
+        })
+        .collect::<eyre::Result<_>>()?;
+    for (id, reply) in node_ids.iter().zip(harness.settle(inits, Kind::Init)?) {
+        if reply.as_ref().and_then(|r| r.get("type")) != Some(&Value::from("init_ok")) {
+            bail!("{id} did not answer init with init_ok, got {reply:?}");
+        }
+    }
+
+    let setup = harness.workload.setup(node_ids);
+    harness.settle(setup, Kind::Setup)?;
+
+    let mut rng = Rng::new(args.seed);
+    let interval = Duration::from_secs_f64(1.0 / args.rate);
+    let start = Instant::now();
+    let mut next_op = start;
+    while next_op < start + args.time_limit {
+        harness.pump(next_op)?;
+        let node = &node_ids[rng.below(node_ids.len() as u64) as usize];
+        let op = harness.workload.op(&mut rng);
+        harness.request(node, op, Kind::Op)?;
+        next_op += interval;
+    }
+
+    harness.pump(Instant::now() + args.recovery)?;
+    let finish = harness.workload.finish(node_ids);
+    harness.settle(finish, Kind::Setup)?;
+
+    Ok(())
+}
@@ -0,0 +1,444 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::{json, Value};
+use vortice::{topology, Rng};
+
+/// The client side of a Maelstrom workload: which requests to make and what
+/// to check about the replies.
+pub trait Workload {
+    /// Requests to make, and have answered, before the run starts.
+    fn setup(&mut self, _node_ids: &[String]) -> Vec<(String, Value)> {
+        Vec::new()
+    }
+
+    fn op(&mut self, rng: &mut Rng) -> Value;
+
+    /// Called for every request made on behalf of the workload, with the
+    /// reply payload or `None` if it timed out.
+    fn complete(&mut self, node: &str, request: &Value, reply: Option<&Value>);
+
+    /// Requests to make once the cluster had time to settle.
+    fn finish(&mut self, _node_ids: &[String]) -> Vec<(String, Value)> {
+        Vec::new()
+    }
+
+    fn check(&self) -> Result<(), String>;
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn Workload>> {
+    match name {
+        "echo" => Some(Box::new(Echo::default())),
+        "unique-ids" => Some(Box::new(UniqueIds::default())),
+        "broadcast" => Some(Box::new(Broadcast::default())),
+        "g-counter" => Some(Box::new(Counter::default())),
+        "pn-counter" => Some(Box::new(Counter {
+            negative: true,
+            ..Counter::default()
+        })),
+        "kafka" => Some(Box::new(Kafka::default())),
+        _ => None,
+    }
+}
+
+fn kind(payload: &Value) -> &str {
+    payload.get("type").and_then(Value::as_str).unwrap_or("")
+}
+
+#[derive(Default)]
+pub struct Echo {
+    next: u64,
+    mismatches: Vec<String>,
+}
+
+impl Workload for Echo {
+    fn op(&mut self, _rng: &mut Rng) -> Value {
+        self.next += 1;
+        json!({ "type": "echo", "echo": format!("Please echo {}", self.next) })
+    }
+
+    fn complete(&mut self, node: &str, request: &Value, reply: Option<&Value>) {
+        let Some(reply) = reply else {
+            return;
+        };
+        if kind(reply) != "echo_ok" || reply.get("echo") != request.get("echo") {
+            self.mismatches
+                .push(format!("{node} answered {request} with {reply}"));
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        match self.mismatches.first() {
+            None => Ok(()),
+            Some(first) => Err(format!(
+                "{} bad echoes, e.g. {first}",
+                self.mismatches.len()
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UniqueIds {
+    seen: HashMap<String, usize>,
+}
+
+impl Workload for UniqueIds {
+    fn op(&mut self, _rng: &mut Rng) -> Value {
+        json!({ "type": "generate" })
+    }
+
+    fn complete(&mut self, _node: &str, _request: &Value, reply: Option<&Value>) {
+        let Some(id) = reply
+            .filter(|r| kind(r) == "generate_ok")
+            .and_then(|r| r.get("id"))
+        else {
+            return;
+        };
+        *self.seen.entry(id.to_string()).or_default() += 1;
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let duplicates: Vec<_> = self.seen.iter().filter(|(_, n)| **n > 1).collect();
+        match duplicates.first() {
+            None => Ok(()),
+            Some((id, n)) => Err(format!(
+                "{} ids were generated more than once, e.g. {id} {n} times",
+                duplicates.len()
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Broadcast {
+    next: u64,
+    attempted: BTreeSet<u64>,
+    acknowledged: BTreeSet<u64>,
+    /// `None` until the node answers its final read.
+    final_reads: BTreeMap<String, Option<BTreeSet<u64>>>,
+}
+
+impl Workload for Broadcast {
+    /// Maelstrom's default grid.
+    fn setup(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        let topology = topology::grid(node_ids);
+        node_ids
+            .iter()
+            .map(|id| {
+                (
+                    id.clone(),
+                    json!({ "type": "topology", "topology": topology }),
+                )
+            })
+            .collect()
+    }
+
+    fn op(&mut self, rng: &mut Rng) -> Value {
+        if rng.chance(0.5) {
+            return json!({ "type": "read" });
+        }
+        self.next += 1;
+        self.attempted.insert(self.next);
+        json!({ "type": "broadcast", "message": self.next })
+    }
+
+    fn complete(&mut self, node: &str, request: &Value, reply: Option<&Value>) {
+        let Some(reply) = reply else {
+            return;
+        };
+        match (kind(request), kind(reply)) {
+            ("broadcast", "broadcast_ok") => {
+                if let Some(message) = request.get("message").and_then(Value::as_u64) {
+                    self.acknowledged.insert(message);
+                }
+            }
+            ("read", "read_ok") if self.final_reads.contains_key(node) => {
+                let messages = reply
+                    .get("messages")
+                    .and_then(Value::as_array)
+                    .map(|ms| ms.iter().filter_map(Value::as_u64).collect())
+                    .unwrap_or_default();
+                self.final_reads.insert(node.to_owned(), Some(messages));
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        node_ids
+            .iter()
+            .map(|id| {
+                self.final_reads.insert(id.clone(), None);
+                (id.clone(), json!({ "type": "read" }))
+            })
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        for (node, read) in &self.final_reads {
+            let Some(read) = read else {
+                return Err(format!("{node} did not answer the final read"));
+            };
+            let lost: Vec<_> = self.acknowledged.difference(read).collect();
+            if !lost.is_empty() {
+                return Err(format!(
+                    "{node} is missing {} of {} acknowledged values, e.g. {:?}",
+                    lost.len(),
+                    self.acknowledged.len(),
+                    &lost[..lost.len().min(5)]
+                ));
+            }
+            if let Some(unexpected) = read.difference(&self.attempted).next() {
+                return Err(format!(
+                    "{node} read {unexpected}, which was never broadcast"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds random deltas and reads the total, which in the end must be what the
+/// acknowledged adds sum to, give or take what the unacknowledged ones do.
+#[derive(Default)]
+pub struct Counter {
+    /// Whether deltas may be negative, as in the `pn-counter` workload.
+    negative: bool,
+    acknowledged: i64,
+    /// Adds that failed or timed out and so may or may not have counted.
+    uncertain: Vec<i64>,
+    final_reads: BTreeMap<String, Option<i64>>,
+}
+
+impl Workload for Counter {
+    fn op(&mut self, rng: &mut Rng) -> Value {
+        if rng.chance(0.5) {
+            return json!({ "type": "read" });
+        }
+        let delta = rng.below(5) as i64;
+        if self.negative && rng.chance(0.5) {
+            return json!({ "type": "add", "delta": -delta });
+        }
+        json!({ "type": "add", "delta": delta })
+    }
+
+    fn complete(&mut self, node: &str, request: &Value, reply: Option<&Value>) {
+        match (kind(request), reply.map(kind)) {
+            ("add", reply) => {
+                let delta = request.get("delta").and_then(Value::as_i64).unwrap_or(0);
+                if reply == Some("add_ok") {
+                    self.acknowledged += delta;
+                } else {
+                    self.uncertain.push(delta);
+                }
+            }
+            ("read", Some("read_ok")) if self.final_reads.contains_key(node) => {
+                let value = reply.and_then(|r| r.get("value")).and_then(Value::as_i64);
+                self.final_reads.insert(node.to_owned(), value);
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        node_ids
+            .iter()
+            .map(|id| {
+                self.final_reads.insert(id.clone(), None);
+                (id.clone(), json!({ "type": "read" }))
+            })
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let low = self.acknowledged + self.uncertain.iter().filter(|d| **d < 0).sum::<i64>();
+        let high = self.acknowledged + self.uncertain.iter().filter(|d| **d > 0).sum::<i64>();
+        for (node, value) in &self.final_reads {
+            match value {
+                None => return Err(format!("{node} did not answer the final read")),
+                Some(value) if !(low..=high).contains(value) => {
+                    return Err(format!("{node} read {value}, expected {low}..={high}"));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many keys the `kafka` workload spreads its sends over.
+const KAFKA_KEYS: u64 = 5;
+
+/// Sends to and polls from a handful of logs, checking that every key hands
+/// out each offset once and that polls return exactly what was sent at those
+/// offsets, skipping nothing.
+#[derive(Default)]
+pub struct Kafka {
+    next: u64,
+    /// Acknowledged sends by key and offset.
+    sent: BTreeMap<String, BTreeMap<u64, u64>>,
+    /// The highest acknowledged commit per key.
+    committed: BTreeMap<String, u64>,
+    final_lists: BTreeMap<String, Option<BTreeMap<String, u64>>>,
+    errors: Vec<String>,
+}
+
+impl Kafka {
+    /// A random key, and an offset in it that some send was acknowledged at.
+    fn pick(&self, rng: &mut Rng) -> (String, u64) {
+        let key = format!("k{}", rng.below(KAFKA_KEYS));
+        let offset = match self.sent.get(&key) {
+            Some(offsets) => {
+                let i = rng.below(offsets.len() as u64) as usize;
+                offsets.keys().nth(i).copied().unwrap_or(0)
+            }
+            None => 0,
+        };
+        (key, offset)
+    }
+
+    fn check_poll(&mut self, node: &str, request: &Value, reply: &Value) {
+        let Some(msgs) = reply.get("msgs").and_then(Value::as_object) else {
+            self.errors
+                .push(format!("{node} answered {request} with {reply}"));
+            return;
+        };
+        for (key, entries) in msgs {
+            let from = request["offsets"][key].as_u64().unwrap_or(0);
+            let entries: Vec<(u64, u64)> = entries
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|e| Some((e.get(0)?.as_u64()?, e.get(1)?.as_u64()?)))
+                .collect();
+            let sent = self.sent.get(key);
+            let mut expected = sent.into_iter().flat_map(|s| s.range(from..)).peekable();
+            for (offset, msg) in entries {
+                if offset < from {
+                    self.errors
+                        .push(format!("{node} polled {key} from {from} but got {offset}"));
+                }
+                // Acknowledged sends below `offset` must have been returned
+                // already: a poll may stop early, but not leave gaps.
+                while let Some((&acked, &acked_msg)) = expected.peek() {
+                    if acked > offset {
+                        break;
+                    }
+                    if acked < offset {
+                        self.errors
+                            .push(format!("{node} skipped {key} offset {acked} in a poll"));
+                    } else if acked_msg != msg {
+                        self.errors.push(format!(
+                            "{node} returned {msg} at {key} offset {offset}, sent {acked_msg}"
+                        ));
+                    }
+                    expected.next();
+                }
+            }
+        }
+    }
+}
+
+impl Workload for Kafka {
+    fn op(&mut self, rng: &mut Rng) -> Value {
+        let roll = rng.below(10);
+        let (key, offset) = self.pick(rng);
+        match roll {
+            0..=4 => {
+                self.next += 1;
+                json!({ "type": "send", "key": key, "msg": self.next })
+            }
+            5..=7 => json!({ "type": "poll", "offsets": { key: offset } }),
+            8 => json!({ "type": "commit_offsets", "offsets": { key: offset } }),
+            _ => json!({ "type": "list_committed_offsets", "keys": [key] }),
+        }
+    }
+
+    fn complete(&mut self, node: &str, request: &Value, reply: Option<&Value>) {
+        let Some(reply) = reply else {
+            return;
+        };
+        match (kind(request), kind(reply)) {
+            ("send", "send_ok") => {
+                let key = request["key"].as_str().unwrap_or("").to_owned();
+                let msg = request["msg"].as_u64().unwrap_or(0);
+                let Some(offset) = reply.get("offset").and_then(Value::as_u64) else {
+                    self.errors
+                        .push(format!("{node} answered {request} with {reply}"));
+                    return;
+                };
+                if let Some(other) = self
+                    .sent
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(offset, msg)
+                {
+                    self.errors.push(format!(
+                        "{node} put both {other} and {msg} at {key} offset {offset}"
+                    ));
+                }
+            }
+            ("poll", "poll_ok") => self.check_poll(node, request, reply),
+            ("commit_offsets", "commit_offsets_ok") => {
+                for (key, offset) in request["offsets"].as_object().into_iter().flatten() {
+                    let offset = offset.as_u64().unwrap_or(0);
+                    let committed = self.committed.entry(key.clone()).or_default();
+                    *committed = (*committed).max(offset);
+                }
+            }
+            ("list_committed_offsets", "list_committed_offsets_ok")
+                if self.final_lists.contains_key(node) =>
+            {
+                let offsets = reply.get("offsets").and_then(Value::as_object).map(|o| {
+                    o.iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_u64()?)))
+                        .collect()
+                });
+                self.final_lists.insert(node.to_owned(), offsets);
+            }
+            _ => {}
+        }
+    }
+
+    /// Polls every key from the start, and lists every committed offset.
+    fn finish(&mut self, node_ids: &[String]) -> Vec<(String, Value)> {
+        let keys: Vec<String> = (0..KAFKA_KEYS).map(|k| format!("k{k}")).collect();
+        let offsets: BTreeMap<&String, u64> = keys.iter().map(|k| (k, 0)).collect();
+        node_ids
+            .iter()
+            .flat_map(|id| {
+                self.final_lists.insert(id.clone(), None);
+                [
+                    (id.clone(), json!({ "type": "poll", "offsets": offsets })),
+                    (
+                        id.clone(),
+                        json!({ "type": "list_committed_offsets", "keys": keys }),
+                    ),
+                ]
+            })
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if let Some(first) = self.errors.first() {
+            return Err(format!("{} errors, e.g. {first}", self.errors.len()));
+        }
+        for (node, offsets) in &self.final_lists {
+            let Some(offsets) = offsets else {
+                return Err(format!("{node} did not list committed offsets"));
+            };
+            for (key, committed) in &self.committed {
+                match offsets.get(key) {
+                    Some(listed) if listed >= committed => {}
+                    listed => {
+                        return Err(format!(
+                            "{node} listed {listed:?} as committed for {key}, \
+                             but {committed} was acknowledged"
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
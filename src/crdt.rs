@@ -0,0 +1,77 @@
+//! State-based CRDTs: data types that every node updates on its own and that
+//! end up the same everywhere once each node has merged in everyone else's
+//! state, whatever order that happens in and however often.
+//!
+//! Every type also has delta mutators, which return the change an update
+//! would make rather than making it. Merging that delta in is the same as
+//! making the update, and it is usually much smaller than the whole state.
+//!
+//! [`Replicator`] does the merging for a node: it gossips the deltas a peer
+//! has not acknowledged yet and folds in what the peers send back.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+mod counter;
+mod map;
+mod register;
+mod replicator;
+mod set;
+
+pub use counter::{GCounter, PnCounter};
+pub use map::LwwMap;
+pub use register::{LwwRegister, MvRegister, Stamp};
+pub use replicator::{Ack, Delta, Replicator};
+pub use set::{GSet, OrSet, TwoPhaseSet};
+
+/// The node that made a change, and how many changes it had made by then.
+/// Tells apart concurrent additions to an [`OrSet`].
+pub type Dot = (String, u64);
+
+pub trait Crdt: Clone + Default + PartialEq + Serialize + DeserializeOwned + 'static {
+    /// Folds `other` into `self`. Has to be commutative, associative and
+    /// idempotent, which is what makes replicas converge.
+    fn merge(&mut self, other: &Self);
+
+    /// The part of `self` that `other` lacks: merging it into `other` gives
+    /// the same as merging all of `self`, usually for a lot less.
+    fn delta(&self, other: &Self) -> Self;
+
+    /// Whether this is the state nothing happened to yet, which merges into
+    /// anything without changing it.
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The properties [`Replicator`] relies on, for the types' tests to check.
+#[cfg(test)]
+mod laws {
+    use std::fmt::Debug;
+
+    use super::Crdt;
+
+    fn join<C: Crdt>(x: &C, y: &C) -> C {
+        let mut x = x.clone();
+        x.merge(y);
+        x
+    }
+
+    /// Checks that merging is commutative, associative and idempotent over
+    /// three replicas, and that a delta brings a replica as far as the whole
+    /// state would.
+    pub fn check<C: Crdt + Debug>(a: &C, b: &C, c: &C) {
+        assert_eq!(join(a, b), join(b, a), "commutative");
+        assert_eq!(join(&join(a, b), c), join(a, &join(b, c)), "associative");
+        for x in [a, b, c] {
+            assert_eq!(&join(x, x), x, "idempotent");
+        }
+        for (x, y) in [(a, b), (b, a), (a, c), (c, b), (&join(a, c), b)] {
+            assert_eq!(
+                join(y, &x.delta(y)),
+                join(y, x),
+                "delta of {x:?} over {y:?}"
+            );
+        }
+        assert!(a.delta(a).is_empty(), "nothing over itself");
+    }
+}
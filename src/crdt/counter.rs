@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::Crdt;
+
+/// A counter that only goes up: what each node added, so merging is taking
+/// the larger count per node.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, node: &str, by: u64) {
+        let delta = self.increment_delta(node, by);
+        self.merge(&delta);
+    }
+
+    /// What [`GCounter::increment`] changes, without changing it: `node`'s
+    /// new count.
+    pub fn increment_delta(&self, node: &str, by: u64) -> Self {
+        let count = self.counts.get(node).copied().unwrap_or(0) + by;
+        GCounter {
+            counts: BTreeMap::from([(node.to_owned(), count)]),
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.counts {
+            let mine = self.counts.entry(node.clone()).or_default();
+            *mine = (*mine).max(*count);
+        }
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        let counts = self
+            .counts
+            .iter()
+            .filter(|(node, count)| other.counts.get(*node) < Some(count))
+            .map(|(node, count)| (node.clone(), *count))
+            .collect();
+        GCounter { counts }
+    }
+}
+
+/// A counter that goes both ways, as two [`GCounter`]s: one for what was
+/// added and one for what was taken away.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PnCounter {
+    inc: GCounter,
+    dec: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, node: &str, by: i64) {
+        let delta = self.add_delta(node, by);
+        self.merge(&delta);
+    }
+
+    /// What [`PnCounter::add`] changes, without changing it.
+    pub fn add_delta(&self, node: &str, by: i64) -> Self {
+        let mut delta = PnCounter::default();
+        if by >= 0 {
+            delta.inc = self.inc.increment_delta(node, by.unsigned_abs());
+        } else {
+            delta.dec = self.dec.increment_delta(node, by.unsigned_abs());
+        }
+        delta
+    }
+
+    pub fn value(&self) -> i64 {
+        self.inc.value() as i64 - self.dec.value() as i64
+    }
+}
+
+impl Crdt for PnCounter {
+    fn merge(&mut self, other: &Self) {
+        self.inc.merge(&other.inc);
+        self.dec.merge(&other.dec);
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        PnCounter {
+            inc: self.inc.delta(&other.inc),
+            dec: self.dec.delta(&other.dec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    #[test]
+    fn g_counter() {
+        let mut a = GCounter::default();
+        a.increment("n1", 3);
+        a.increment("n2", 1);
+        let mut b = a.clone();
+        b.increment("n1", 2);
+        let mut c = GCounter::default();
+        c.increment("n3", 5);
+
+        laws::check(&a, &b, &c);
+        let mut all = a.clone();
+        all.merge(&b);
+        all.merge(&c);
+        assert_eq!(all.value(), 11);
+        assert_eq!(a.increment_delta("n1", 1).counts["n1"], 4);
+    }
+
+    #[test]
+    fn pn_counter() {
+        let mut a = PnCounter::default();
+        a.add("n1", 3);
+        a.add("n1", -1);
+        let mut b = a.clone();
+        b.add("n2", -4);
+        let mut c = PnCounter::default();
+        c.add("n3", 2);
+
+        laws::check(&a, &b, &c);
+        let mut all = a.clone();
+        all.merge(&b);
+        all.merge(&c);
+        assert_eq!(all.value(), 0);
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Crdt, LwwRegister};
+
+/// A map with an [`LwwRegister`] per key. Removing a key writes a tombstone
+/// in its place, so a removal and a write race like any two writes.
+///
+/// Keys have to serialize as JSON object keys: strings or integers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(
+    serialize = "K: Serialize, V: Serialize",
+    deserialize = "K: DeserializeOwned + Ord, V: DeserializeOwned"
+))]
+pub struct LwwMap<K, V> {
+    entries: BTreeMap<K, LwwRegister<Option<V>>>,
+}
+
+impl<K, V> Default for LwwMap<K, V> {
+    fn default() -> Self {
+        LwwMap {
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> LwwMap<K, V> {
+    pub fn insert(&mut self, node: &str, key: K, value: V) {
+        self.entries.entry(key).or_default().set(node, Some(value));
+    }
+
+    pub fn remove(&mut self, node: &str, key: K) {
+        self.entries.entry(key).or_default().set(node, None);
+    }
+
+    /// What [`LwwMap::insert`] changes, without changing it: the one entry.
+    pub fn insert_delta(&self, node: &str, key: K, value: V) -> Self {
+        self.write_delta(node, key, Some(value))
+    }
+
+    /// What [`LwwMap::remove`] changes, without changing it: the tombstone.
+    pub fn remove_delta(&self, node: &str, key: K) -> Self {
+        self.write_delta(node, key, None)
+    }
+
+    fn write_delta(&self, node: &str, key: K, value: Option<V>) -> Self {
+        let register = match self.entries.get(&key) {
+            Some(register) => register.set_delta(node, value),
+            None => LwwRegister::default().set_delta(node, value),
+        };
+        LwwMap {
+            entries: BTreeMap::from([(key, register)]),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)?.get()?.as_ref()
+    }
+
+    /// The live entries, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|(key, register)| Some((key, register.get()?.as_ref()?)))
+    }
+}
+
+impl<K, V> Crdt for LwwMap<K, V>
+where
+    K: Ord + Clone + Serialize + DeserializeOwned + 'static,
+    V: Clone + PartialEq + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        for (key, register) in &other.entries {
+            self.entries.entry(key.clone()).or_default().merge(register);
+        }
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        let entries = self
+            .entries
+            .iter()
+            .filter_map(|(key, register)| {
+                let delta = match other.entries.get(key) {
+                    Some(theirs) => register.delta(theirs),
+                    None => register.clone(),
+                };
+                (!delta.is_empty()).then(|| (key.clone(), delta))
+            })
+            .collect();
+        LwwMap { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    #[test]
+    fn lww_map() {
+        let mut a = LwwMap::default();
+        a.insert("n1", "k".to_owned(), 1);
+        let mut b = a.clone();
+        b.remove("n2", "k".to_owned());
+        let mut c = LwwMap::default();
+        c.insert("n3", "j".to_owned(), 2);
+        c.insert("n3", "k".to_owned(), 3);
+
+        laws::check(&a, &b, &c);
+        let mut all = a.clone();
+        all.merge(&b);
+        assert_eq!(all.get(&"k".to_owned()), None, "removed");
+        all.merge(&c);
+        // n3 wrote k without having seen the removal, at an older clock.
+        let entries: Vec<_> = all.iter().collect();
+        assert_eq!(entries, [(&"j".to_owned(), &2)]);
+    }
+}
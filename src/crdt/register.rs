@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::Crdt;
+
+/// Orders writes to an [`LwwRegister`]: a Lamport clock, ties broken by the
+/// node that wrote.
+pub type Stamp = (u64, String);
+
+/// A register where the last write wins. Writes are stamped one past the
+/// newest stamp the writer has seen, so a write always beats every write its
+/// node had merged in before; concurrent writes are settled by node id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct LwwRegister<T> {
+    value: Option<(Stamp, T)>,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        LwwRegister { value: None }
+    }
+}
+
+impl<T> LwwRegister<T> {
+    pub fn set(&mut self, node: &str, value: T) {
+        *self = self.set_delta(node, value);
+    }
+
+    /// What [`LwwRegister::set`] changes, without changing it: since the new
+    /// write beats the old one, that is the whole register.
+    pub fn set_delta(&self, node: &str, value: T) -> Self {
+        let clock = self.stamp().map_or(0, |(clock, _)| *clock);
+        LwwRegister {
+            value: Some(((clock + 1, node.to_owned()), value)),
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref().map(|(_, value)| value)
+    }
+
+    pub fn stamp(&self) -> Option<&Stamp> {
+        self.value.as_ref().map(|(stamp, _)| stamp)
+    }
+}
+
+impl<T> Crdt for LwwRegister<T>
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        if other.stamp() > self.stamp() {
+            self.value = other.value.clone();
+        }
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        if self.stamp() > other.stamp() {
+            self.clone()
+        } else {
+            Self::default()
+        }
+    }
+}
+
+/// Which writes each node had made, as far as a write had seen.
+type Version = BTreeMap<String, u64>;
+
+/// A register that keeps every value written concurrently rather than
+/// picking one: a write replaces only the values its node had seen, so a
+/// reader gets all the writes nobody has overwritten yet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned"))]
+pub struct MvRegister<T> {
+    values: Vec<(Version, T)>,
+}
+
+impl<T> Default for MvRegister<T> {
+    fn default() -> Self {
+        MvRegister { values: Vec::new() }
+    }
+}
+
+impl<T> MvRegister<T> {
+    pub fn set(&mut self, node: &str, value: T) {
+        *self = self.set_delta(node, value);
+    }
+
+    /// What [`MvRegister::set`] changes, without changing it: the new value
+    /// alone, which replaces every value this replica has seen.
+    pub fn set_delta(&self, node: &str, value: T) -> Self {
+        let mut version = Version::new();
+        for (seen, _) in &self.values {
+            for (writer, count) in seen {
+                let mine = version.entry(writer.clone()).or_default();
+                *mine = (*mine).max(*count);
+            }
+        }
+        *version.entry(node.to_owned()).or_default() += 1;
+        MvRegister {
+            values: vec![(version, value)],
+        }
+    }
+
+    /// The values written concurrently; empty if nothing was written.
+    pub fn get(&self) -> impl Iterator<Item = &T> + '_ {
+        self.values.iter().map(|(_, value)| value)
+    }
+}
+
+/// Whether every write `b` had seen, `a` had seen too, and then some.
+fn dominates(a: &Version, b: &Version) -> bool {
+    a != b
+        && b.iter()
+            .all(|(node, count)| a.get(node).is_some_and(|c| c >= count))
+}
+
+impl<T> Crdt for MvRegister<T>
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        let mut values = self.values.clone();
+        for entry in &other.values {
+            if !values.contains(entry) {
+                values.push(entry.clone());
+            }
+        }
+        self.values = values
+            .iter()
+            .filter(|(version, _)| !values.iter().any(|(v, _)| dominates(v, version)))
+            .cloned()
+            .collect();
+        // Each write has its own version, so this puts every replica's
+        // values in the same order.
+        self.values.sort_by(|(a, _), (b, _)| a.cmp(b));
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        let values = self
+            .values
+            .iter()
+            .filter(|entry| !other.values.contains(entry))
+            .cloned()
+            .collect();
+        MvRegister { values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    #[test]
+    fn lww_register() {
+        let mut base = LwwRegister::default();
+        base.set("n1", 'a');
+        let mut a = base.clone();
+        a.set("n1", 'b');
+        let mut b = base;
+        b.set("n2", 'c');
+        let mut c = LwwRegister::default();
+        c.set("n3", 'd');
+
+        laws::check(&a, &b, &c);
+        let mut ab = a.clone();
+        ab.merge(&b);
+        assert_eq!(ab.get(), Some(&'c'), "same clock, higher node");
+        ab.set("n1", 'e');
+        assert_eq!(ab.stamp(), Some(&(3, "n1".to_owned())));
+    }
+
+    #[test]
+    fn mv_register() {
+        let mut base = MvRegister::default();
+        base.set("n1", 1);
+        let mut a = base.clone();
+        a.set("n1", 2);
+        let mut b = base;
+        b.set("n2", 3);
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut c = ab.clone();
+        c.set("n3", 4);
+
+        laws::check(&a, &b, &c);
+        let mut concurrent: Vec<_> = ab.get().collect();
+        concurrent.sort();
+        assert_eq!(concurrent, [&2, &3]);
+        let mut all = ab.clone();
+        all.merge(&c);
+        assert_eq!(all.get().collect::<Vec<_>>(), [&4]);
+    }
+}
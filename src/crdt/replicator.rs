@@ -0,0 +1,420 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use eyre::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Crdt;
+use crate::{Ctx, Msg, RpcError};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+/// How many deltas to hold on to for peers that have not acknowledged them;
+/// a peer that falls further behind is sent the full state instead.
+const DEFAULT_BUFFER: usize = 1024;
+
+/// The changes a replicator made after sequence number `since` and up to
+/// `upto`, or, without `since`, its full state as of `upto`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "C: Crdt")]
+pub struct Delta<C> {
+    pub since: Option<u64>,
+    pub upto: u64,
+    pub state: C,
+    /// The last sequence number the sender received from the recipient;
+    /// `None` if it never heard from it, say because it restarted.
+    pub heard: Option<u64>,
+}
+
+/// The reply to a [`Delta`]: how far the recipient is now up to date, or
+/// `None` if it lost track of the sender, which then sends its full state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ack {
+    pub upto: Option<u64>,
+}
+
+/// Keeps a node's copy of a [`Crdt`] in step with its peers' copies.
+///
+/// The node owns the replicator and wires it to its own messages: it calls
+/// [`Replicator::gossip`] on a timer, wrapping each [`Delta`] in a payload
+/// variant of its own, and answers that variant with the [`Ack`]
+/// [`Replicator::receive`] returns:
+///
+/// ```ignore
+/// enum Payload {
+///     Replicate(Delta<MyCrdt>),
+///     ReplicateOk(Ack),
+/// }
+/// ...
+/// Event::Timer(Timer::Gossip) => self.replicator.gossip(
+///     ctx,
+///     |node: &mut MyNode| &mut node.replicator,
+///     Payload::Replicate,
+/// )?,
+/// ...
+/// Payload::Replicate(delta) => {
+///     let ack = self.replicator.receive(&input.src, delta);
+///     ctx.reply(&input, Payload::ReplicateOk(ack))?;
+/// }
+/// ```
+///
+/// Local changes go in through [`Replicator::apply`] as deltas made by the
+/// state's delta mutators. Every delta gets the next sequence number and is
+/// buffered until all peers have acknowledged it, so each round a peer is
+/// sent just the deltas after its last ack, joined into one. A peer that
+/// never acknowledged anything (we restarted), that fell behind what the
+/// buffer holds (a long partition), or that has not heard from us since it
+/// last acknowledged something (it restarted) is sent the full state
+/// instead. Until a peer acknowledges us we send it our state even when
+/// that is empty, so a restarted node makes itself known.
+///
+/// A node that restarted without its state starts over from nothing, and
+/// its own entry in the state along with it; mutators applied to that lose
+/// whatever the peers still remember of it. [`Replicator::caught_up`] tells
+/// when every peer's full state, and so what they have of ours, is back.
+#[derive(Debug, Clone)]
+pub struct Replicator<C> {
+    state: C,
+    /// The last sequence number handed out.
+    seq: u64,
+    /// Deltas some peer has not acknowledged yet, by sequence number.
+    buffer: BTreeMap<u64, C>,
+    /// The last sequence number each peer acknowledged.
+    acked: BTreeMap<String, u64>,
+    /// The last sequence number received from each peer.
+    received: BTreeMap<String, u64>,
+    timeout: Duration,
+    capacity: usize,
+}
+
+impl<C: Crdt> Default for Replicator<C> {
+    fn default() -> Self {
+        Replicator {
+            state: C::default(),
+            seq: 0,
+            buffer: BTreeMap::new(),
+            acked: BTreeMap::new(),
+            received: BTreeMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+            capacity: DEFAULT_BUFFER,
+        }
+    }
+}
+
+impl<C: Crdt> Replicator<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long to wait for a peer to acknowledge gossip before sending it
+    /// again on a later round.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many deltas to buffer before peers that have not acknowledged
+    /// the oldest ones are sent the full state instead.
+    pub fn with_buffer(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn state(&self) -> &C {
+        &self.state
+    }
+
+    /// Whether the full state of each of `peers` has been merged in since
+    /// this replicator started.
+    pub fn caught_up<'a>(&self, mut peers: impl Iterator<Item = &'a str>) -> bool {
+        // Deltas only count once a full state came first.
+        peers.all(|peer| self.received.contains_key(peer))
+    }
+
+    /// Merges a delta made by one of the state's delta mutators into the
+    /// local copy; it goes out with the next gossip.
+    pub fn apply(&mut self, delta: C) {
+        if delta.is_empty() {
+            return;
+        }
+        self.state.merge(&delta);
+        self.seq += 1;
+        self.buffer.insert(self.seq, delta);
+        while self.buffer.len() > self.capacity {
+            self.buffer.pop_first();
+        }
+    }
+
+    /// Merges in what `from` sent, returning what to acknowledge.
+    pub fn receive(&mut self, from: &str, delta: &Delta<C>) -> Ack {
+        if delta.heard.is_none() && self.acked.remove(from).is_some() {
+            eprintln!("{from} lost track of us, sending it our full state");
+        }
+        self.state.merge(&delta.state);
+        let received = self.received.get(from).copied();
+        match delta.since {
+            // Changes on top of some we never got: we must have restarted.
+            Some(since) if received.is_none_or(|r| r < since) => Ack { upto: None },
+            Some(_) => {
+                let upto = received.map_or(delta.upto, |r| r.max(delta.upto));
+                self.received.insert(from.to_owned(), upto);
+                Ack { upto: Some(upto) }
+            }
+            // A full state starts over, as `from` may have restarted.
+            None => {
+                self.received.insert(from.to_owned(), delta.upto);
+                Ack {
+                    upto: Some(delta.upto),
+                }
+            }
+        }
+    }
+
+    /// What `peer` is missing, if anything.
+    fn delta_for(&self, peer: &str) -> Option<Delta<C>> {
+        let Some(&acked) = self.acked.get(peer) else {
+            return Some(self.full(peer));
+        };
+        if acked >= self.seq {
+            return None;
+        }
+        match self.buffer.first_key_value() {
+            Some((&first, _)) if first <= acked + 1 => {
+                let mut state = C::default();
+                for delta in self.buffer.range(acked + 1..).map(|(_, d)| d) {
+                    state.merge(delta);
+                }
+                Some(Delta {
+                    since: Some(acked),
+                    upto: self.seq,
+                    state,
+                    heard: self.received.get(peer).copied(),
+                })
+            }
+            _ => Some(self.full(peer)),
+        }
+    }
+
+    fn full(&self, peer: &str) -> Delta<C> {
+        Delta {
+            since: None,
+            upto: self.seq,
+            state: self.state.clone(),
+            heard: self.received.get(peer).copied(),
+        }
+    }
+
+    /// Sends every peer what it has not acknowledged yet, as the payload
+    /// `wrap` makes of it. `replicator` finds this replicator in the node
+    /// once the acknowledgement comes back.
+    pub fn gossip<N, E, P>(
+        &mut self,
+        ctx: &mut Ctx<N, E>,
+        replicator: fn(&mut N) -> &mut Self,
+        wrap: impl Fn(Delta<C>) -> P,
+    ) -> eyre::Result<()>
+    where
+        N: 'static,
+        P: Serialize,
+    {
+        let peers: Vec<String> = ctx.peers().map(String::from).collect();
+
+        // Deltas every peer has acknowledged are of no more use; peers with
+        // no ack at all get the full state, so they need none of them.
+        let oldest = peers.iter().filter_map(|p| self.acked.get(p)).min();
+        if let Some(&oldest) = oldest {
+            self.buffer = self.buffer.split_off(&(oldest + 1));
+        }
+
+        for peer in peers {
+            let Some(delta) = self.delta_for(&peer) else {
+                continue;
+            };
+            ctx.rpc(
+                peer.clone(),
+                wrap(delta),
+                self.timeout,
+                move |node, reply: Result<Msg<Value>, RpcError>, _ctx| {
+                    if let Ok(ack) = reply.and_then(|msg| parse::<Ack>(msg.body.payload)) {
+                        replicator(node).acknowledged(peer, ack);
+                    }
+                    Ok(())
+                },
+            )
+            .context("gossip")?;
+        }
+        Ok(())
+    }
+
+    fn acknowledged(&mut self, peer: String, ack: Ack) {
+        match ack.upto {
+            Some(upto) => {
+                let acked = self.acked.entry(peer).or_default();
+                *acked = (*acked).max(upto);
+            }
+            None => {
+                self.acked.remove(&peer);
+            }
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(payload: Value) -> Result<T, RpcError> {
+    serde_json::from_value(payload).map_err(RpcError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crdt::GCounter,
+        sim::{Fault, Record, Sim, SimConfig},
+        Event, Init, Node,
+    };
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    #[serde(rename_all = "snake_case")]
+    enum Payload {
+        Add { delta: u64 },
+        AddOk,
+        Replicate(Delta<GCounter>),
+        ReplicateOk(Ack),
+    }
+
+    #[derive(Debug, Clone)]
+    struct Tick;
+
+    struct CounterNode {
+        counter: Replicator<GCounter>,
+    }
+
+    impl Node<usize, Payload, Tick> for CounterNode {
+        fn from_init(buffer: usize, _init: Init, ctx: &mut Ctx<Self, Tick>) -> eyre::Result<Self> {
+            ctx.every(Duration::from_millis(50), Tick);
+            Ok(CounterNode {
+                counter: Replicator::new().with_buffer(buffer),
+            })
+        }
+
+        fn step(
+            &mut self,
+            input: Event<Payload, Tick>,
+            ctx: &mut Ctx<Self, Tick>,
+        ) -> eyre::Result<()> {
+            let input = match input {
+                Event::Message(input) => input,
+                Event::Timer(Tick) => {
+                    return self.counter.gossip(
+                        ctx,
+                        |node: &mut Self| &mut node.counter,
+                        Payload::Replicate,
+                    );
+                }
+                Event::Eof => return Ok(()),
+            };
+            match &input.body.payload {
+                Payload::Add { delta } => {
+                    let delta = self.counter.state().increment_delta(ctx.node_id(), *delta);
+                    self.counter.apply(delta);
+                    ctx.reply(&input, Payload::AddOk)?;
+                }
+                Payload::Replicate(delta) => {
+                    let ack = self.counter.receive(&input.src, delta);
+                    ctx.reply(&input, Payload::ReplicateOk(ack))?;
+                }
+                Payload::AddOk | Payload::ReplicateOk(_) => {}
+            }
+            Ok(())
+        }
+    }
+
+    type CounterSim = Sim<usize, CounterNode, Payload, Tick>;
+
+    fn cluster(buffer: usize) -> CounterSim {
+        let config = SimConfig {
+            loss: 0.1,
+            ..SimConfig::default()
+        };
+        Sim::new(config, 3, buffer).unwrap()
+    }
+
+    fn add(sim: &mut CounterSim, node: &str, delta: u64) {
+        let reply: Msg<Payload> = sim
+            .call("c1", node, Payload::Add { delta }, Duration::from_secs(1))
+            .unwrap();
+        assert!(matches!(reply.body.payload, Payload::AddOk));
+    }
+
+    fn values(sim: &CounterSim) -> Vec<u64> {
+        ["n0", "n1", "n2"]
+            .iter()
+            .map(|id| sim.node(id).unwrap().counter.state().value())
+            .collect()
+    }
+
+    /// Gossip sent over the last `window` of the run.
+    fn recent_gossip(sim: &CounterSim, window: Duration) -> usize {
+        let since = sim.now() - window;
+        sim.history()
+            .iter()
+            .filter(|record| match record {
+                Record::Sent { at, msg } => *at >= since && msg.body.payload["type"] == "replicate",
+                _ => false,
+            })
+            .count()
+    }
+
+    #[test]
+    fn restarted_node_catches_up_while_idle() {
+        let mut sim = cluster(16);
+        add(&mut sim, "n0", 5);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(values(&sim), [5, 5, 5]);
+
+        sim.apply(Fault::Crash("n1".to_owned())).unwrap();
+        sim.apply(Fault::Restart {
+            node: "n1".to_owned(),
+            lose_state: true,
+        })
+        .unwrap();
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(values(&sim), [5, 5, 5]);
+
+        add(&mut sim, "n1", 1);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(values(&sim), [6, 6, 6]);
+        assert_eq!(
+            recent_gossip(&sim, Duration::from_millis(500)),
+            0,
+            "settled"
+        );
+    }
+
+    #[test]
+    fn partitioned_node_gets_full_state_once_buffer_overflows() {
+        let mut sim = cluster(2);
+        add(&mut sim, "n0", 1);
+        sim.run_for(Duration::from_secs(1)).unwrap();
+
+        sim.apply(Fault::Partition(vec![
+            vec!["n0".to_owned(), "n1".to_owned()],
+            vec!["n2".to_owned()],
+        ]))
+        .unwrap();
+        for _ in 0..5 {
+            add(&mut sim, "n0", 1);
+            add(&mut sim, "n2", 2);
+        }
+        sim.run_for(Duration::from_secs(1)).unwrap();
+        assert_eq!(values(&sim), [6, 6, 11]);
+
+        sim.apply(Fault::Heal).unwrap();
+        sim.run_for(Duration::from_secs(2)).unwrap();
+        assert_eq!(values(&sim), [16, 16, 16]);
+        assert_eq!(
+            recent_gossip(&sim, Duration::from_millis(500)),
+            0,
+            "settled"
+        );
+    }
+}
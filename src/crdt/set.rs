@@ -0,0 +1,332 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{Crdt, Dot};
+
+/// A set that only grows; merging is taking the union.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct GSet<T> {
+    elements: BTreeSet<T>,
+}
+
+impl<T> Default for GSet<T> {
+    fn default() -> Self {
+        GSet {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord> GSet<T> {
+    /// Returns whether `value` was new to the set.
+    pub fn insert(&mut self, value: T) -> bool {
+        self.elements.insert(value)
+    }
+
+    /// What [`GSet::insert`] changes, without changing it: `value` alone,
+    /// or nothing if the set already has it.
+    pub fn insert_delta(&self, value: T) -> Self {
+        let mut delta = GSet::default();
+        if !self.contains(&value) {
+            delta.insert(value);
+        }
+        delta
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.elements.contains(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    /// The elements in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.elements.iter()
+    }
+
+    pub fn elements(&self) -> &BTreeSet<T> {
+        &self.elements
+    }
+}
+
+impl<T: Ord> FromIterator<T> for GSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(values: I) -> Self {
+        GSet {
+            elements: values.into_iter().collect(),
+        }
+    }
+}
+
+impl<T> Crdt for GSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        self.elements.difference(&other.elements).cloned().collect()
+    }
+}
+
+/// A set that elements can be removed from once, and never added back to
+/// afterwards: a [`GSet`] of additions and one of removals.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct TwoPhaseSet<T> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T> Default for TwoPhaseSet<T> {
+    fn default() -> Self {
+        TwoPhaseSet {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> TwoPhaseSet<T> {
+    /// Returns whether `value` is in the set now, which it never is again
+    /// once removed.
+    pub fn insert(&mut self, value: T) -> bool {
+        if self.removed.contains(&value) {
+            return false;
+        }
+        self.added.insert(value);
+        true
+    }
+
+    /// Returns whether `value` was in the set.
+    pub fn remove(&mut self, value: &T) -> bool {
+        if !self.contains(value) {
+            return false;
+        }
+        self.removed.insert(value.clone())
+    }
+
+    /// What [`TwoPhaseSet::insert`] changes, without changing it.
+    pub fn insert_delta(&self, value: T) -> Self {
+        let mut delta = TwoPhaseSet::default();
+        if !self.removed.contains(&value) {
+            delta.added = self.added.insert_delta(value);
+        }
+        delta
+    }
+
+    /// What [`TwoPhaseSet::remove`] changes, without changing it.
+    pub fn remove_delta(&self, value: &T) -> Self {
+        let mut delta = TwoPhaseSet::default();
+        if self.contains(value) {
+            delta.removed.insert(value.clone());
+        }
+        delta
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.added.contains(value) && !self.removed.contains(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.added.iter().filter(|v| !self.removed.contains(v))
+    }
+}
+
+impl<T> Crdt for TwoPhaseSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        TwoPhaseSet {
+            added: self.added.delta(&other.added),
+            removed: self.removed.delta(&other.removed),
+        }
+    }
+}
+
+/// A set where removing only undoes the additions the remover had seen, so
+/// an addition concurrent with a removal wins.
+///
+/// Every addition is tagged with a [`Dot`]; an element is in the set while
+/// some tag of it has not been removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(serialize = "T: Serialize", deserialize = "T: DeserializeOwned + Ord"))]
+pub struct OrSet<T> {
+    added: BTreeSet<(T, Dot)>,
+    removed: BTreeSet<Dot>,
+    /// The last dot each node handed out.
+    clock: BTreeMap<String, u64>,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        OrSet {
+            added: BTreeSet::new(),
+            removed: BTreeSet::new(),
+            clock: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> OrSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + 'static,
+{
+    pub fn insert(&mut self, node: &str, value: T) {
+        let delta = self.insert_delta(node, value);
+        self.merge(&delta);
+    }
+
+    /// Returns whether `value` was in the set.
+    pub fn remove(&mut self, value: &T) -> bool {
+        let delta = self.remove_delta(value);
+        self.merge(&delta);
+        !delta.removed.is_empty()
+    }
+
+    /// What [`OrSet::insert`] changes, without changing it: `value` under a
+    /// new dot of `node`'s.
+    pub fn insert_delta(&self, node: &str, value: T) -> Self {
+        let seq = self.clock.get(node).copied().unwrap_or(0) + 1;
+        let dot = (node.to_owned(), seq);
+        OrSet {
+            added: BTreeSet::from([(value, dot)]),
+            removed: BTreeSet::new(),
+            clock: BTreeMap::from([(node.to_owned(), seq)]),
+        }
+    }
+
+    /// What [`OrSet::remove`] changes, without changing it: the dots of
+    /// `value` this replica has seen.
+    pub fn remove_delta(&self, value: &T) -> Self {
+        OrSet {
+            added: BTreeSet::new(),
+            removed: self.tags(value).map(|(_, dot)| dot.clone()).collect(),
+            clock: BTreeMap::new(),
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.tags(value).next().is_some()
+    }
+
+    /// The elements in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        let mut last = None;
+        self.added.iter().filter_map(move |(value, _)| {
+            if last == Some(value) {
+                return None;
+            }
+            last = Some(value);
+            Some(value)
+        })
+    }
+
+    fn tags<'a>(&'a self, value: &'a T) -> impl Iterator<Item = &'a (T, Dot)> + 'a {
+        self.added
+            .range((value.clone(), (String::new(), 0))..)
+            .take_while(move |(v, _)| v == value)
+    }
+}
+
+impl<T> Crdt for OrSet<T>
+where
+    T: Ord + Clone + Serialize + DeserializeOwned + 'static,
+{
+    fn merge(&mut self, other: &Self) {
+        self.removed.extend(other.removed.iter().cloned());
+        self.added.extend(other.added.iter().cloned());
+        let removed = &self.removed;
+        self.added.retain(|(_, dot)| !removed.contains(dot));
+        for (node, seq) in &other.clock {
+            let mine = self.clock.entry(node.clone()).or_default();
+            *mine = (*mine).max(*seq);
+        }
+    }
+
+    fn delta(&self, other: &Self) -> Self {
+        OrSet {
+            added: self.added.difference(&other.added).cloned().collect(),
+            removed: self.removed.difference(&other.removed).cloned().collect(),
+            clock: self
+                .clock
+                .iter()
+                .filter(|(node, seq)| other.clock.get(*node) < Some(seq))
+                .map(|(node, seq)| (node.clone(), *seq))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crdt::laws;
+
+    #[test]
+    fn g_set() {
+        let a: GSet<u32> = [1, 2].into_iter().collect();
+        let b: GSet<u32> = [2, 3].into_iter().collect();
+        let c: GSet<u32> = [4].into_iter().collect();
+        laws::check(&a, &b, &c);
+        assert!(a.insert_delta(1).is_empty());
+        assert_eq!(a.delta(&b).iter().collect::<Vec<_>>(), [&1]);
+    }
+
+    #[test]
+    fn two_phase_set() {
+        let mut base = TwoPhaseSet::default();
+        base.insert(1);
+        base.insert(2);
+        let mut a = base.clone();
+        a.remove(&1);
+        let mut b = base.clone();
+        b.insert(3);
+        let mut c = base;
+        c.remove(&2);
+
+        laws::check(&a, &b, &c);
+        let mut all = a.clone();
+        all.merge(&b);
+        all.merge(&c);
+        assert_eq!(all.iter().collect::<Vec<_>>(), [&3]);
+        assert!(!all.insert(1), "removed for good");
+    }
+
+    #[test]
+    fn or_set() {
+        let mut base = OrSet::default();
+        base.insert("n1", 'x');
+        let mut a = base.clone();
+        a.remove(&'x');
+        let mut b = base.clone();
+        b.insert("n2", 'x');
+        let mut c = OrSet::default();
+        c.insert("n3", 'y');
+
+        laws::check(&a, &b, &c);
+        let mut ab = a.clone();
+        ab.merge(&b);
+        assert!(ab.contains(&'x'), "a concurrent add wins");
+        let mut a_base = a.clone();
+        a_base.merge(&base);
+        assert!(!a_base.contains(&'x'), "a seen add is removed");
+        assert_eq!(ab.remove_delta(&'x').removed.len(), 1);
+    }
+}
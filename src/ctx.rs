@@ -0,0 +1,273 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    timer::{TimerId, Timers},
+    Body, Error, Msg, Output,
+};
+
+/// The shortest period [`Ctx::every`] fires at.
+const MIN_PERIOD: Duration = Duration::from_millis(1);
+
+pub type Callback<N, E = ()> =
+    Box<dyn FnOnce(&mut N, Result<Msg<Value>, RpcError>, &mut Ctx<N, E>) -> eyre::Result<()>>;
+
+#[derive(Debug)]
+pub enum RpcError {
+    /// No reply arrived before the deadline given to [`Ctx::rpc`].
+    Timeout,
+    /// The other side answered with an `error` payload.
+    Maelstrom(Error),
+    /// A reply arrived but did not match the expected payload.
+    Malformed(serde_json::Error),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Timeout => write!(f, "rpc timed out"),
+            RpcError::Maelstrom(e) => write!(f, "rpc failed: {e}"),
+            RpcError::Malformed(e) => write!(f, "malformed rpc reply: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+impl RpcError {
+    /// The Maelstrom code a handler would pass on for this failure.
+    pub fn code(&self) -> crate::ErrorCode {
+        match self {
+            RpcError::Timeout => crate::ErrorCode::Timeout,
+            RpcError::Maelstrom(e) => e.code,
+            RpcError::Malformed(_) => crate::ErrorCode::MalformedRequest,
+        }
+    }
+}
+
+struct Pending<N, E> {
+    deadline: Instant,
+    callback: Callback<N, E>,
+}
+
+/// Everything a node can do besides mutating its own state: emit messages,
+/// issue requests whose replies are routed back through a callback and
+/// schedule `E` to be handed back to it later as [`crate::Event::Timer`].
+pub struct Ctx<N, E = ()> {
+    node_id: String,
+    node_ids: Vec<String>,
+    now: Instant,
+    next_msg_id: usize,
+    output: Box<dyn Output>,
+    pending: HashMap<usize, Pending<N, E>>,
+    timers: Timers<E>,
+}
+
+impl<N, E> Ctx<N, E> {
+    pub fn new(output: impl Output + 'static) -> Self {
+        Self {
+            node_id: String::new(),
+            node_ids: Vec::new(),
+            now: Instant::now(),
+            next_msg_id: 0,
+            output: Box::new(output),
+            pending: HashMap::new(),
+            timers: Timers::new(),
+        }
+    }
+
+    pub fn set_membership(&mut self, node_id: String, node_ids: Vec<String>) {
+        self.node_id = node_id;
+        self.node_ids = node_ids;
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// Every node in the cluster, including this one, as given by init.
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Every node in the cluster but this one.
+    pub fn peers(&self) -> impl Iterator<Item = &str> + '_ {
+        self.node_ids
+            .iter()
+            .map(String::as_str)
+            .filter(move |id| *id != self.node_id)
+    }
+
+    /// The time of the event being handled. Under the simulator this is
+    /// virtual time, so nodes should not call [`Instant::now`] themselves.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub(crate) fn set_now(&mut self, now: Instant) {
+        self.now = now;
+    }
+
+    pub fn next_msg_id(&mut self) -> usize {
+        let id = self.next_msg_id;
+        self.next_msg_id += 1;
+        id
+    }
+
+    pub fn write<P: Serialize>(&mut self, msg: &Msg<P>) -> eyre::Result<()> {
+        let msg = Msg {
+            src: msg.src.clone(),
+            dst: msg.dst.clone(),
+            body: Body {
+                id: msg.body.id,
+                in_reply_to: msg.body.in_reply_to,
+                payload: serde_json::to_value(&msg.body.payload)
+                    .context("Serialize::serialize failed")?,
+            },
+        };
+        self.output.send(msg)
+    }
+
+    /// Sends `payload` to `dst` without expecting a reply.
+    pub fn send<P: Serialize>(&mut self, dst: impl Into<String>, payload: P) -> eyre::Result<()> {
+        let id = self.next_msg_id();
+        self.write(&Msg {
+            src: self.node_id.clone(),
+            dst: dst.into(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                payload,
+            },
+        })
+    }
+
+    pub fn reply<P: Serialize, Q>(&mut self, request: &Msg<Q>, payload: P) -> eyre::Result<()> {
+        let id = self.next_msg_id();
+        self.write(&Msg {
+            src: request.dst.clone(),
+            dst: request.src.clone(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: request.body.id,
+                payload,
+            },
+        })
+    }
+
+    pub fn reply_error<Q>(&mut self, request: &Msg<Q>, error: Error) -> eyre::Result<()> {
+        self.reply(request, error)
+    }
+
+    /// Sends `payload` to `dst` and arranges for `callback` to run with the
+    /// reply, or with [`RpcError::Timeout`] if none shows up within `timeout`.
+    /// An `error` reply reaches the callback as [`RpcError::Maelstrom`].
+    ///
+    /// Returns the msg_id of the request.
+    pub fn rpc<P, R, F>(
+        &mut self,
+        dst: impl Into<String>,
+        payload: P,
+        timeout: Duration,
+        callback: F,
+    ) -> eyre::Result<usize>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+        F: FnOnce(&mut N, Result<Msg<R>, RpcError>, &mut Ctx<N, E>) -> eyre::Result<()> + 'static,
+    {
+        let id = self.next_msg_id();
+        self.write(&Msg {
+            src: self.node_id.clone(),
+            dst: dst.into(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                payload,
+            },
+        })
+        .context("send rpc request")?;
+
+        self.pending.insert(
+            id,
+            Pending {
+                deadline: self.now + timeout,
+                callback: Box::new(move |node, reply, ctx| {
+                    let reply = reply.and_then(|msg| {
+                        if Error::is_error_payload(&msg.body.payload) {
+                            let error = serde_json::from_value(msg.body.payload)
+                                .map_err(RpcError::Malformed)?;
+                            return Err(RpcError::Maelstrom(error));
+                        }
+                        msg.parse::<R>().map_err(RpcError::Malformed)
+                    });
+                    callback(node, reply, ctx)
+                }),
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// Forgets about an outstanding request; a late reply is then handed to
+    /// the node as an ordinary message.
+    pub fn cancel(&mut self, msg_id: usize) -> bool {
+        self.pending.remove(&msg_id).is_some()
+    }
+
+    /// Hands `event` back to the node once `delay` has passed.
+    pub fn schedule(&mut self, delay: Duration, event: E) -> TimerId {
+        self.timers.schedule(self.now + delay, event, None)
+    }
+
+    /// Hands `event` back to the node every `period`, starting one period
+    /// from now, until the timer is cancelled. Periods under a millisecond
+    /// are raised to one, as a zero period would fire forever without time
+    /// passing.
+    pub fn every(&mut self, period: Duration, event: E) -> TimerId {
+        let period = period.max(MIN_PERIOD);
+        self.timers.schedule(self.now + period, event, Some(period))
+    }
+
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.timers.cancel(id)
+    }
+
+    pub(crate) fn take_callback(&mut self, in_reply_to: usize) -> Option<Callback<N, E>> {
+        self.pending.remove(&in_reply_to).map(|p| p.callback)
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let rpc = self.pending.values().map(|p| p.deadline).min();
+        rpc.into_iter().chain(self.timers.next_deadline()).min()
+    }
+
+    pub(crate) fn pop_due_timer(&mut self, now: Instant) -> Option<E>
+    where
+        E: Clone,
+    {
+        self.timers.pop_due(now)
+    }
+
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Vec<Callback<N, E>> {
+        let mut expired: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.deadline <= now)
+            .map(|(&id, _)| id)
+            .collect();
+        expired.sort_unstable();
+
+        expired
+            .into_iter()
+            .filter_map(|id| self.take_callback(id))
+            .collect()
+    }
+}
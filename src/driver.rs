@@ -0,0 +1,272 @@
+use std::{marker::PhantomData, time::Instant};
+
+use eyre::Context;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{Body, Ctx, Error, ErrorCode, Event, InitPayload, Msg, Node, Output, RpcError};
+
+/// One node together with its [`Ctx`], fed raw messages and the passing of
+/// time by whoever owns it: [`crate::main_loop`] from stdin and the wall
+/// clock, [`crate::sim::Sim`] from its virtual network and clock.
+///
+/// Takes care of everything that is the same for every node: waiting for
+/// init, routing replies to rpc callbacks, firing timers, and turning
+/// unparseable messages and handler failures into error replies. Only I/O
+/// failures and a failing [`Node::from_init`], or any init after one, are
+/// returned as errors.
+pub struct Driver<S, N, P, E = ()> {
+    state: Option<S>,
+    node: Option<N>,
+    ctx: Ctx<N, E>,
+    _payload: PhantomData<fn() -> P>,
+}
+
+impl<S, N, P, E> Driver<S, N, P, E>
+where
+    N: Node<S, P, E>,
+    P: DeserializeOwned,
+    E: Clone,
+{
+    pub fn new(state: S, output: impl Output + 'static) -> Self {
+        Self {
+            state: Some(state),
+            node: None,
+            ctx: Ctx::new(output),
+            _payload: PhantomData,
+        }
+    }
+
+    pub fn node(&self) -> Option<&N> {
+        self.node.as_ref()
+    }
+
+    pub fn ctx(&self) -> &Ctx<N, E> {
+        &self.ctx
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.node.as_ref().and(self.ctx.next_deadline())
+    }
+
+    /// Parses and handles one line of input.
+    pub fn handle_line(&mut self, line: &str, now: Instant) -> eyre::Result<()> {
+        self.ctx.set_now(now);
+        match serde_json::from_str(line) {
+            Ok(input) => self.handle(input, now),
+            Err(e) => {
+                eprintln!("Could not deserialize {line:?}: {e}");
+                if let Some(request) = header_of(line) {
+                    let error = Error::new(ErrorCode::MalformedRequest, e.to_string());
+                    reject(&mut self.ctx, &request, error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn handle(&mut self, input: Msg<Value>, now: Instant) -> eyre::Result<()> {
+        self.ctx.set_now(now);
+        let Some(node) = self.node.as_mut() else {
+            return self.init(input);
+        };
+
+        if let Some(callback) = input
+            .body
+            .in_reply_to
+            .and_then(|id| self.ctx.take_callback(id))
+        {
+            if let Err(e) = callback(node, Ok(input), &mut self.ctx) {
+                eprintln!("rpc callback failed: {e:#}");
+            }
+            return Ok(());
+        }
+
+        let request = input.header();
+        let input: Msg<P> = match input.parse() {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("Could not deserialize message from {}: {e}", request.src);
+                return reject(&mut self.ctx, &request, parse_error(&e));
+            }
+        };
+        if let Err(e) = node.step(Event::Message(input), &mut self.ctx) {
+            eprintln!("Node::step failed: {e:#}");
+            reject(&mut self.ctx, &request, to_error(&e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Times out rpcs and fires timers that are due at `now`.
+    pub fn fire(&mut self, now: Instant) -> eyre::Result<()> {
+        self.ctx.set_now(now);
+        let Some(node) = self.node.as_mut() else {
+            return Ok(());
+        };
+
+        for callback in self.ctx.take_expired(now) {
+            if let Err(e) = callback(node, Err(RpcError::Timeout), &mut self.ctx) {
+                eprintln!("rpc timeout callback failed: {e:#}");
+            }
+        }
+        while let Some(event) = self.ctx.pop_due_timer(now) {
+            if let Err(e) = node.step(Event::Timer(event), &mut self.ctx) {
+                eprintln!("Node::step failed on timer: {e:#}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tells the node no more input is coming.
+    pub fn shutdown(&mut self, now: Instant) -> eyre::Result<()> {
+        self.ctx.set_now(now);
+        if let Some(node) = self.node.as_mut() {
+            if let Err(e) = node.step(Event::Eof, &mut self.ctx) {
+                eprintln!("Node::step failed on eof: {e:#}");
+            }
+        }
+
+        Ok(())
+    }
+
+    // Nothing but init is accepted until we know who we are and who the
+    // other nodes are.
+    fn init(&mut self, input: Msg<Value>) -> eyre::Result<()> {
+        let request = input.header();
+        if input.body.payload.get("type").and_then(Value::as_str) != Some("init") {
+            eprintln!("message before init from {}", request.src);
+            let error = Error::new(ErrorCode::TemporarilyUnavailable, "node not initialized");
+            return reject(&mut self.ctx, &request, error);
+        }
+
+        let init = match input.parse::<InitPayload>() {
+            Ok(Msg {
+                body:
+                    Body {
+                        payload: InitPayload::Init(init),
+                        ..
+                    },
+                ..
+            }) => init,
+            Ok(_) => unreachable!("payload type was checked to be init"),
+            Err(e) => {
+                eprintln!("Could not deserialize init from {}: {e}", request.src);
+                return reject(&mut self.ctx, &request, parse_error(&e));
+            }
+        };
+
+        // A node whose init failed has no state left to try again with.
+        let Some(state) = self.state.take() else {
+            let error = Error::new(ErrorCode::Crash, "node failed to initialize");
+            reject(&mut self.ctx, &request, error)?;
+            eyre::bail!("init after Node::from_init failed");
+        };
+        self.ctx
+            .set_membership(init.node_id.clone(), init.node_ids.clone());
+        match N::from_init(state, init, &mut self.ctx) {
+            Ok(node) => self.node = Some(node),
+            Err(e) => {
+                reject(&mut self.ctx, &request, to_error(&e))?;
+                return Err(e).context("Node::from_init failed");
+            }
+        }
+
+        self.ctx
+            .reply(&request, InitPayload::InitOk)
+            .context("send init_ok")
+    }
+}
+
+/// Answers `request` with `error`, unless it is itself a reply or does not
+/// expect one: erroring back at replies could bounce between two nodes forever.
+fn reject<N, E>(ctx: &mut Ctx<N, E>, request: &Msg<()>, error: Error) -> eyre::Result<()> {
+    if request.body.id.is_none() || request.body.in_reply_to.is_some() {
+        return Ok(());
+    }
+    ctx.reply_error(request, error).context("send error reply")
+}
+
+/// Best-effort recovery of who sent a line that is not a valid message, so
+/// they can at least be told about it.
+fn header_of(line: &str) -> Option<Msg<()>> {
+    let value: Value = serde_json::from_str(line).ok()?;
+    Some(Msg {
+        src: value.get("src")?.as_str()?.to_owned(),
+        dst: value.get("dest")?.as_str()?.to_owned(),
+        body: Body {
+            id: Some(value.pointer("/body/msg_id")?.as_u64()? as usize),
+            in_reply_to: value
+                .pointer("/body/in_reply_to")
+                .and_then(Value::as_u64)
+                .map(|id| id as usize),
+            payload: (),
+        },
+    })
+}
+
+/// A payload whose `type` the node does not know is not supported; one whose
+/// type is known but whose fields do not fit is malformed.
+pub(crate) fn parse_error(e: &serde_json::Error) -> Error {
+    let text = e.to_string();
+    if text.starts_with("unknown variant") {
+        Error::new(ErrorCode::NotSupported, text)
+    } else {
+        Error::new(ErrorCode::MalformedRequest, text)
+    }
+}
+
+/// Maps a handler failure to the error reply the requester gets. Anything that
+/// is not already a Maelstrom error is reported as a crash, since we cannot
+/// tell how far the handler got.
+fn to_error(e: &eyre::Report) -> Error {
+    if let Some(e) = e.downcast_ref::<Error>() {
+        return e.clone();
+    }
+    if let Some(e) = e.downcast_ref::<RpcError>() {
+        return Error::new(e.code(), e.to_string());
+    }
+    Error::new(ErrorCode::Crash, format!("{e:#}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::Init;
+
+    #[derive(Debug, Deserialize)]
+    enum Payload {}
+
+    struct Failing;
+
+    impl Node<(), Payload> for Failing {
+        fn from_init(_state: (), _init: Init, _ctx: &mut Ctx<Self>) -> eyre::Result<Self> {
+            eyre::bail!("no")
+        }
+
+        fn step(&mut self, _input: Event<Payload>, _ctx: &mut Ctx<Self>) -> eyre::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn init_after_failed_init_is_an_error() {
+        let (tx, rx) = mpsc::channel();
+        let mut driver = Driver::<(), Failing, Payload>::new((), tx);
+        let init = r#"{"src":"c1","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#;
+        let now = Instant::now();
+        assert!(driver.handle_line(init, now).is_err());
+        assert!(driver.handle_line(init, now).is_err());
+        let codes: Vec<_> = rx
+            .try_iter()
+            .map(|msg| msg.body.payload["code"].clone())
+            .collect();
+        assert_eq!(codes, [13, 13]);
+        assert!(driver.node().is_none());
+    }
+}
@@ -0,0 +1,241 @@
+//! A client for Maelstrom's key-value services: `seq-kv` (sequentially
+//! consistent), `lin-kv` (linearizable) and `lww-kv` (last write wins).
+//!
+//! Requests go out through [`Ctx::rpc`], so like any rpc their outcome is
+//! handed to a callback along with the node, from whatever step or callback
+//! the reply (or timeout) arrives in:
+//!
+//! ```ignore
+//! Kv::seq().read(ctx, "counter", |node: &mut MyNode, value: Result<u64, KvError>, ctx| {
+//!     ...
+//! })?;
+//! ```
+
+use std::{fmt, time::Duration};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{Ctx, ErrorCode, Msg, RpcError};
+
+mod service;
+
+pub use service::{Consistency, Service};
+
+pub const SEQ_KV: &str = "seq-kv";
+pub const LIN_KV: &str = "lin-kv";
+pub const LWW_KV: &str = "lww-kv";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The messages the KV services understand and answer with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
+
+#[derive(Debug)]
+pub enum KvError {
+    /// Error 20: a read, or a cas without `create_if_not_exists`, of a key
+    /// nobody wrote.
+    KeyDoesNotExist,
+    /// Error 22: a cas found something other than `from`.
+    PreconditionFailed,
+    /// Anything else, including timeouts and replies that did not decode.
+    Rpc(RpcError),
+}
+
+impl fmt::Display for KvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KvError::KeyDoesNotExist => write!(f, "key does not exist"),
+            KvError::PreconditionFailed => write!(f, "precondition failed"),
+            KvError::Rpc(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for KvError {}
+
+impl From<RpcError> for KvError {
+    fn from(e: RpcError) -> Self {
+        match e.code() {
+            ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+            _ => KvError::Rpc(e),
+        }
+    }
+}
+
+impl KvError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            KvError::KeyDoesNotExist => ErrorCode::KeyDoesNotExist,
+            KvError::PreconditionFailed => ErrorCode::PreconditionFailed,
+            KvError::Rpc(e) => e.code(),
+        }
+    }
+}
+
+fn unexpected(payload: Payload) -> KvError {
+    KvError::Rpc(RpcError::Malformed(serde::de::Error::custom(format!(
+        "unexpected reply {payload:?}"
+    ))))
+}
+
+fn to_value(value: impl Serialize) -> eyre::Result<Value> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// One of the KV services, and how long to wait for its replies.
+#[derive(Debug, Clone)]
+pub struct Kv {
+    service: String,
+    timeout: Duration,
+}
+
+impl Kv {
+    pub fn new(service: impl Into<String>) -> Self {
+        Kv {
+            service: service.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn seq() -> Self {
+        Kv::new(SEQ_KV)
+    }
+
+    pub fn lin() -> Self {
+        Kv::new(LIN_KV)
+    }
+
+    pub fn lww() -> Self {
+        Kv::new(LWW_KV)
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn read<N, E, K, V, F>(&self, ctx: &mut Ctx<N, E>, key: K, callback: F) -> eyre::Result<()>
+    where
+        K: Serialize,
+        V: DeserializeOwned,
+        F: FnOnce(&mut N, Result<V, KvError>, &mut Ctx<N, E>) -> eyre::Result<()> + 'static,
+    {
+        let request = Payload::Read {
+            key: to_value(key)?,
+        };
+        ctx.rpc(
+            self.service.as_str(),
+            request,
+            self.timeout,
+            move |node, reply: Result<Msg<Payload>, RpcError>, ctx| {
+                let value = match reply.map(|msg| msg.body.payload) {
+                    Ok(Payload::ReadOk { value }) => serde_json::from_value(value)
+                        .map_err(|e| KvError::Rpc(RpcError::Malformed(e))),
+                    Ok(other) => Err(unexpected(other)),
+                    Err(e) => Err(e.into()),
+                };
+                callback(node, value, ctx)
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn write<N, E, K, V, F>(
+        &self,
+        ctx: &mut Ctx<N, E>,
+        key: K,
+        value: V,
+        callback: F,
+    ) -> eyre::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<(), KvError>, &mut Ctx<N, E>) -> eyre::Result<()> + 'static,
+    {
+        let request = Payload::Write {
+            key: to_value(key)?,
+            value: to_value(value)?,
+        };
+        ctx.rpc(
+            self.service.as_str(),
+            request,
+            self.timeout,
+            move |node, reply: Result<Msg<Payload>, RpcError>, ctx| {
+                let done = match reply.map(|msg| msg.body.payload) {
+                    Ok(Payload::WriteOk) => Ok(()),
+                    Ok(other) => Err(unexpected(other)),
+                    Err(e) => Err(e.into()),
+                };
+                callback(node, done, ctx)
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Sets `key` to `to` if it holds `from`. With `create_if_not_exists` a
+    /// missing key is set to `to` rather than failing.
+    pub fn cas<N, E, K, V, F>(
+        &self,
+        ctx: &mut Ctx<N, E>,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+        callback: F,
+    ) -> eyre::Result<()>
+    where
+        K: Serialize,
+        V: Serialize,
+        F: FnOnce(&mut N, Result<(), KvError>, &mut Ctx<N, E>) -> eyre::Result<()> + 'static,
+    {
+        let request = Payload::Cas {
+            key: to_value(key)?,
+            from: to_value(from)?,
+            to: to_value(to)?,
+            create_if_not_exists,
+        };
+        ctx.rpc(
+            self.service.as_str(),
+            request,
+            self.timeout,
+            move |node, reply: Result<Msg<Payload>, RpcError>, ctx| {
+                let done = match reply.map(|msg| msg.body.payload) {
+                    Ok(Payload::CasOk) => Ok(()),
+                    Ok(other) => Err(unexpected(other)),
+                    Err(e) => Err(e.into()),
+                };
+                callback(node, done, ctx)
+            },
+        )?;
+        Ok(())
+    }
+}
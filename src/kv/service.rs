@@ -0,0 +1,458 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use serde_json::Value;
+
+use super::{Payload, LIN_KV, LWW_KV, SEQ_KV};
+use crate::{driver::parse_error, Body, Error, ErrorCode, Msg, Rng};
+
+/// How many replicas an `lww-kv` stand-in keeps.
+const LWW_REPLICAS: usize = 3;
+/// The longest an `lww-kv` write takes to reach the other replicas.
+const LWW_MAX_LAG: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// `lin-kv`: every operation takes effect at once, in the order
+    /// requests arrive.
+    Linearizable,
+    /// `seq-kv`: writes take effect at once, but reads may be served from
+    /// any state no older than what the same client last saw or wrote.
+    Sequential,
+    /// `lww-kv`: every request goes to one of several replicas, which learn
+    /// about each other's writes a little later and keep the newest one.
+    LastWriteWins,
+}
+
+/// An in-process stand-in for one of Maelstrom's KV services, for running
+/// nodes that use them without Maelstrom: hand it every message sent to
+/// [`Service::name`] and route back what it returns.
+pub struct Service {
+    name: String,
+    store: Store,
+    rng: Rng,
+    next_msg_id: usize,
+}
+
+enum Store {
+    Linearizable(BTreeMap<String, Value>),
+    Sequential(Sequential),
+    LastWriteWins(LastWriteWins),
+}
+
+type Reply = Result<Payload, Error>;
+
+impl Service {
+    pub fn new(name: impl Into<String>, consistency: Consistency, seed: u64) -> Self {
+        let store = match consistency {
+            Consistency::Linearizable => Store::Linearizable(BTreeMap::new()),
+            Consistency::Sequential => Store::Sequential(Sequential::default()),
+            Consistency::LastWriteWins => Store::LastWriteWins(LastWriteWins::default()),
+        };
+        Service {
+            name: name.into(),
+            store,
+            rng: Rng::new(seed),
+            next_msg_id: 0,
+        }
+    }
+
+    pub fn lin() -> Self {
+        Service::new(LIN_KV, Consistency::Linearizable, 0)
+    }
+
+    pub fn seq(seed: u64) -> Self {
+        Service::new(SEQ_KV, Consistency::Sequential, seed)
+    }
+
+    pub fn lww(seed: u64) -> Self {
+        Service::new(LWW_KV, Consistency::LastWriteWins, seed)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Handles a message sent to the service at `now`, returning the reply
+    /// if it was a request.
+    pub fn handle(&mut self, request: Msg<Value>, now: Instant) -> Option<Msg<Value>> {
+        let id = request.body.id?;
+        if request.body.in_reply_to.is_some() {
+            return None;
+        }
+
+        let header = request.header();
+        let reply = match request.parse::<Payload>() {
+            Ok(msg) => self.apply(&msg.src, msg.body.payload, now),
+            Err(e) => Err(parse_error(&e)),
+        };
+        let payload = match reply {
+            Ok(payload) => serde_json::to_value(payload),
+            Err(error) => serde_json::to_value(error),
+        }
+        .expect("KV payloads serialize");
+
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        Some(Msg {
+            src: self.name.clone(),
+            dst: header.src,
+            body: Body {
+                id: Some(msg_id),
+                in_reply_to: Some(id),
+                payload,
+            },
+        })
+    }
+
+    fn apply(&mut self, client: &str, request: Payload, now: Instant) -> Reply {
+        match request {
+            Payload::Read { key } => {
+                let key = key.to_string();
+                let value = match &mut self.store {
+                    Store::Linearizable(map) => map.get(&key).cloned(),
+                    Store::Sequential(seq) => seq.read(client, &key, &mut self.rng),
+                    Store::LastWriteWins(lww) => lww.read(&key, now, &mut self.rng),
+                };
+                let value = value.ok_or_else(|| missing(&key))?;
+                Ok(Payload::ReadOk { value })
+            }
+            Payload::Write { key, value } => {
+                let key = key.to_string();
+                match &mut self.store {
+                    Store::Linearizable(map) => {
+                        map.insert(key, value);
+                    }
+                    Store::Sequential(seq) => seq.write(client, key, value),
+                    Store::LastWriteWins(lww) => lww.write(key, value, now, &mut self.rng),
+                }
+                Ok(Payload::WriteOk)
+            }
+            Payload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => {
+                let key = key.to_string();
+                let current = match &mut self.store {
+                    Store::Linearizable(map) => map.get(&key).cloned(),
+                    Store::Sequential(seq) => seq.latest(client, &key),
+                    Store::LastWriteWins(lww) => lww.read(&key, now, &mut self.rng),
+                };
+                match current {
+                    None if !create_if_not_exists => return Err(missing(&key)),
+                    Some(current) if current != from => {
+                        return Err(Error::new(
+                            ErrorCode::PreconditionFailed,
+                            format!("expected {from}, but had {current}"),
+                        ));
+                    }
+                    _ => {}
+                }
+                match &mut self.store {
+                    Store::Linearizable(map) => {
+                        map.insert(key, to);
+                    }
+                    Store::Sequential(seq) => seq.write(client, key, to),
+                    Store::LastWriteWins(lww) => lww.write_last(key, to, now, &mut self.rng),
+                }
+                Ok(Payload::CasOk)
+            }
+            Payload::ReadOk { .. } | Payload::WriteOk | Payload::CasOk => Err(Error::new(
+                ErrorCode::NotSupported,
+                "the KV service only answers read, write and cas",
+            )),
+        }
+    }
+}
+
+fn missing(key: &str) -> Error {
+    Error::new(
+        ErrorCode::KeyDoesNotExist,
+        format!("key {key} does not exist"),
+    )
+}
+
+/// A store that remembers every version of every key, so reads can be
+/// served from the past.
+#[derive(Default)]
+struct Sequential {
+    /// Each key's values, with the version that wrote them.
+    history: BTreeMap<String, Vec<(u64, Value)>>,
+    version: u64,
+    /// The oldest version each client may still be shown.
+    floors: BTreeMap<String, u64>,
+}
+
+impl Sequential {
+    fn value_at(&self, key: &str, version: u64) -> Option<Value> {
+        let writes = self.history.get(key)?;
+        let i = writes.partition_point(|(v, _)| *v <= version);
+        i.checked_sub(1).map(|i| writes[i].1.clone())
+    }
+
+    /// Reads from some version between the client's floor and now, which
+    /// becomes its new floor.
+    fn read(&mut self, client: &str, key: &str, rng: &mut Rng) -> Option<Value> {
+        let floor = self.floors.get(client).copied().unwrap_or(0);
+        let at = floor + rng.below(self.version - floor + 1);
+        self.floors.insert(client.to_owned(), at);
+        self.value_at(key, at)
+    }
+
+    /// Reads the current value, as a cas does before writing.
+    fn latest(&mut self, client: &str, key: &str) -> Option<Value> {
+        self.floors.insert(client.to_owned(), self.version);
+        self.value_at(key, self.version)
+    }
+
+    fn write(&mut self, client: &str, key: String, value: Value) {
+        self.version += 1;
+        self.history
+            .entry(key)
+            .or_default()
+            .push((self.version, value));
+        self.floors.insert(client.to_owned(), self.version);
+    }
+}
+
+/// Which of two writes wins: the later one, ties broken by replica.
+type Stamp = (u64, usize);
+
+/// Replicas that each take writes on their own and share them lazily.
+struct LastWriteWins {
+    replicas: Vec<BTreeMap<String, (Stamp, Value)>>,
+    /// Writes on their way to other replicas, and when they get there.
+    in_transit: Vec<(Instant, usize, String, Stamp, Value)>,
+    clock: u64,
+    /// The replica the current request went to.
+    current: usize,
+}
+
+impl Default for LastWriteWins {
+    fn default() -> Self {
+        LastWriteWins {
+            replicas: vec![BTreeMap::new(); LWW_REPLICAS],
+            in_transit: Vec::new(),
+            clock: 0,
+            current: 0,
+        }
+    }
+}
+
+impl LastWriteWins {
+    /// Delivers whatever has arrived by `now` and picks the replica to
+    /// serve the next request.
+    fn pick(&mut self, now: Instant, rng: &mut Rng) -> usize {
+        let (arrived, in_transit) = std::mem::take(&mut self.in_transit)
+            .into_iter()
+            .partition(|(at, ..)| *at <= now);
+        self.in_transit = in_transit;
+        for (_, replica, key, stamp, value) in arrived {
+            self.merge(replica, key, stamp, value);
+        }
+
+        self.current = rng.below(self.replicas.len() as u64) as usize;
+        self.current
+    }
+
+    fn merge(&mut self, replica: usize, key: String, stamp: Stamp, value: Value) {
+        let entry = self.replicas[replica]
+            .entry(key)
+            .or_insert((stamp, value.clone()));
+        if entry.0 < stamp {
+            *entry = (stamp, value);
+        }
+    }
+
+    fn read(&mut self, key: &str, now: Instant, rng: &mut Rng) -> Option<Value> {
+        let replica = self.pick(now, rng);
+        self.replicas[replica].get(key).map(|(_, v)| v.clone())
+    }
+
+    fn write(&mut self, key: String, value: Value, now: Instant, rng: &mut Rng) {
+        self.pick(now, rng);
+        self.write_last(key, value, now, rng);
+    }
+
+    /// Writes to the replica the previous read went to, as a cas does.
+    fn write_last(&mut self, key: String, value: Value, now: Instant, rng: &mut Rng) {
+        self.clock += 1;
+        let stamp = (self.clock, self.current);
+        self.merge(self.current, key.clone(), stamp, value.clone());
+        for replica in (0..self.replicas.len()).filter(|r| *r != self.current) {
+            let at = now + rng.duration(Duration::ZERO, LWW_MAX_LAG);
+            self.in_transit
+                .push((at, replica, key.clone(), stamp, value.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A service and a clock to send it requests with.
+    struct Client {
+        service: Service,
+        epoch: Instant,
+        next_msg_id: usize,
+    }
+
+    impl Client {
+        fn new(service: Service) -> Self {
+            Client {
+                service,
+                epoch: Instant::now(),
+                next_msg_id: 0,
+            }
+        }
+
+        /// Sends `request` from `client` at `at` past the epoch and returns
+        /// the answer, or the code of the error it got.
+        fn send(&mut self, client: &str, request: Payload, at: Duration) -> Result<Payload, u32> {
+            let id = self.next_msg_id;
+            self.next_msg_id += 1;
+            let request = Msg {
+                src: client.to_owned(),
+                dst: self.service.name().to_owned(),
+                body: Body {
+                    id: Some(id),
+                    in_reply_to: None,
+                    payload: serde_json::to_value(request).unwrap(),
+                },
+            };
+            let reply = self.service.handle(request, self.epoch + at).unwrap();
+            assert_eq!(reply.dst, client);
+            assert_eq!(reply.body.in_reply_to, Some(id));
+            if reply.body.payload["type"] == "error" {
+                let error: Error = serde_json::from_value(reply.body.payload).unwrap();
+                return Err(error.code.into());
+            }
+            Ok(serde_json::from_value(reply.body.payload).unwrap())
+        }
+
+        fn read(&mut self, client: &str, key: &str, at: Duration) -> Result<Value, u32> {
+            match self.send(client, Payload::Read { key: json!(key) }, at)? {
+                Payload::ReadOk { value } => Ok(value),
+                reply => panic!("read got {reply:?}"),
+            }
+        }
+
+        fn write(&mut self, client: &str, key: &str, value: Value, at: Duration) {
+            let write = Payload::Write {
+                key: json!(key),
+                value,
+            };
+            assert_eq!(self.send(client, write, at), Ok(Payload::WriteOk));
+        }
+
+        fn cas(
+            &mut self,
+            key: &str,
+            from: u64,
+            to: u64,
+            create: bool,
+            at: Duration,
+        ) -> Result<(), u32> {
+            let cas = Payload::Cas {
+                key: json!(key),
+                from: json!(from),
+                to: json!(to),
+                create_if_not_exists: create,
+            };
+            self.send("c1", cas, at)
+                .map(|reply| assert_eq!(reply, Payload::CasOk))
+        }
+    }
+
+    #[test]
+    fn seq_reads_stay_above_the_floor() {
+        let mut kv = Client::new(Service::seq(1));
+        // What c2 read of x after each write, as a number: -1 for missing.
+        let mut seen = Vec::new();
+        for i in 0..100 {
+            kv.write("c1", "x", json!(i), Duration::ZERO);
+            // The writer always sees its own writes.
+            assert_eq!(kv.read("c1", "x", Duration::ZERO), Ok(json!(i)));
+            let read = kv.read("c2", "x", Duration::ZERO);
+            seen.push(read.map_or(-1, |value| value.as_i64().unwrap()));
+        }
+        // Another client may lag, but never further than it already saw.
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]), "{seen:?}");
+        assert!(
+            seen.iter().zip(0..).any(|(s, i)| *s < i),
+            "c2 never lagged: {seen:?}"
+        );
+
+        // Once it writes, it is caught up.
+        kv.write("c2", "y", json!(0), Duration::ZERO);
+        assert_eq!(kv.read("c2", "x", Duration::ZERO), Ok(json!(99)));
+    }
+
+    #[test]
+    fn lin_reads_are_fresh() {
+        let mut kv = Client::new(Service::lin());
+        for i in 0..100 {
+            kv.write("c1", "x", json!(i), Duration::ZERO);
+            assert_eq!(kv.read("c2", "x", Duration::ZERO), Ok(json!(i)));
+            assert_eq!(kv.cas("x", i, i + 100, false, Duration::ZERO), Ok(()));
+            assert_eq!(kv.read("c3", "x", Duration::ZERO), Ok(json!(i + 100)));
+        }
+    }
+
+    #[test]
+    fn lww_replicas_converge() {
+        let mut kv = Client::new(Service::lww(1));
+        let start = Duration::from_secs(1);
+        kv.write("c1", "x", json!(1), start);
+
+        let reads: Vec<_> = (0..20).map(|_| kv.read("c2", "x", start)).collect();
+        assert!(reads.contains(&Ok(json!(1))), "{reads:?}");
+        assert!(
+            reads.contains(&Err(20)),
+            "replicas never diverged: {reads:?}"
+        );
+
+        let later = start + LWW_MAX_LAG;
+        for _ in 0..20 {
+            assert_eq!(kv.read("c2", "x", later), Ok(json!(1)));
+        }
+
+        // Concurrent writes to different replicas settle on the last one.
+        for i in 2..20 {
+            kv.write("c1", "x", json!(i), later);
+        }
+        let settled = later + LWW_MAX_LAG;
+        for _ in 0..20 {
+            assert_eq!(kv.read("c2", "x", settled), Ok(json!(19)));
+        }
+    }
+
+    #[test]
+    fn cas_errors() {
+        for service in [Service::lin(), Service::seq(1), Service::lww(1)] {
+            let name = service.name().to_owned();
+            let mut kv = Client::new(service);
+            let now = Duration::ZERO;
+            assert_eq!(kv.read("c1", "x", now), Err(20), "{name}");
+            assert_eq!(kv.cas("x", 0, 1, false, now), Err(20), "{name}");
+            assert_eq!(kv.cas("x", 0, 1, true, now), Ok(()), "{name}");
+
+            // Let lww-kv's replicas catch up before comparing against them.
+            let now = now + LWW_MAX_LAG;
+            assert_eq!(kv.cas("x", 0, 2, false, now), Err(22), "{name}");
+            assert_eq!(kv.cas("x", 1, 2, false, now), Ok(()), "{name}");
+            assert_eq!(
+                kv.read("c1", "x", now + LWW_MAX_LAG),
+                Ok(json!(2)),
+                "{name}"
+            );
+        }
+    }
+}
@@ -0,0 +1,23 @@
+pub mod crdt;
+mod ctx;
+mod driver;
+mod error;
+pub mod kv;
+mod message;
+mod node;
+mod output;
+mod rng;
+mod runtime;
+pub mod sim;
+mod timer;
+pub mod topology;
+
+pub use ctx::{Callback, Ctx, RpcError};
+pub use driver::Driver;
+pub use error::{Error, ErrorCode};
+pub use message::{Body, Init, InitPayload, Msg};
+pub use node::{Event, Node};
+pub use output::{JsonLines, Output};
+pub use rng::Rng;
+pub use runtime::main_loop;
+pub use timer::TimerId;
@@ -0,0 +1,95 @@
+use std::io::Write;
+
+use eyre::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename = "Message")]
+pub struct Msg<P> {
+    pub src: String,
+    #[serde(rename = "dest")]
+    pub dst: String,
+    pub body: Body<P>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Body<P> {
+    #[serde(rename = "msg_id")]
+    pub id: Option<usize>,
+    pub in_reply_to: Option<usize>,
+    #[serde(flatten)]
+    pub payload: P,
+}
+
+impl<P> Msg<P> {
+    /// Turns a request into the skeleton of its reply: swaps `src`/`dst`, points
+    /// `in_reply_to` at the request and takes the next id from `id`, if given.
+    pub fn into_reply(self, id: Option<&mut usize>) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            body: Body {
+                id: id.map(|id| {
+                    let mid = *id;
+                    *id += 1;
+                    mid
+                }),
+                in_reply_to: self.body.id,
+                payload: self.body.payload,
+            },
+        }
+    }
+
+    /// The routing part of the message, without its payload.
+    pub fn header(&self) -> Msg<()> {
+        Msg {
+            src: self.src.clone(),
+            dst: self.dst.clone(),
+            body: Body {
+                id: self.body.id,
+                in_reply_to: self.body.in_reply_to,
+                payload: (),
+            },
+        }
+    }
+
+    pub fn send(&self, output: &mut impl Write) -> eyre::Result<()>
+    where
+        P: Serialize,
+    {
+        serde_json::to_writer(&mut *output, self).context("Serialize::serialize failed")?;
+        output.write_all(b"\n").context("Write::failed")?;
+
+        Ok(())
+    }
+}
+
+impl Msg<Value> {
+    /// Decodes a message read with an untyped payload into a concrete one.
+    pub fn parse<P: DeserializeOwned>(self) -> serde_json::Result<Msg<P>> {
+        Ok(Msg {
+            src: self.src,
+            dst: self.dst,
+            body: Body {
+                id: self.body.id,
+                in_reply_to: self.body.in_reply_to,
+                payload: serde_json::from_value(self.body.payload)?,
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum InitPayload {
+    Init(Init),
+    InitOk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Init {
+    pub node_id: String,
+    pub node_ids: Vec<String>,
+}
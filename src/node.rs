@@ -0,0 +1,22 @@
+use crate::{Ctx, Init, Msg};
+
+#[derive(Debug, Clone)]
+pub enum Event<P, E = ()> {
+    Message(Msg<P>),
+    /// A timer set with [`Ctx::schedule`] or [`Ctx::every`] went off.
+    Timer(E),
+    /// stdin was closed, no more messages will arrive.
+    Eof,
+}
+
+/// A challenge node driven by [`crate::main_loop`].
+///
+/// `S` is whatever initial state the binary hands to the runtime, `P` is the
+/// payload enum of the workload the node answers and `E` is what it schedules
+/// on its timers. Replies to requests sent through [`Ctx::rpc`] go to their
+/// callback instead of [`Node::step`].
+pub trait Node<S, P, E = ()>: Sized {
+    fn from_init(state: S, init: Init, ctx: &mut Ctx<Self, E>) -> eyre::Result<Self>;
+
+    fn step(&mut self, input: Event<P, E>, ctx: &mut Ctx<Self, E>) -> eyre::Result<()>;
+}
@@ -0,0 +1,28 @@
+use std::{io::Write, sync::mpsc::Sender};
+
+use eyre::Context;
+use serde_json::Value;
+
+use crate::Msg;
+
+/// Where a node's outgoing messages go. The runtime writes them to stdout,
+/// tests and simulators can collect them in memory instead.
+pub trait Output {
+    fn send(&mut self, msg: Msg<Value>) -> eyre::Result<()>;
+}
+
+/// Maelstrom's wire format: one JSON message per line.
+pub struct JsonLines<W>(pub W);
+
+impl<W: Write> Output for JsonLines<W> {
+    fn send(&mut self, msg: Msg<Value>) -> eyre::Result<()> {
+        msg.send(&mut self.0)?;
+        self.0.flush().context("Write::flush failed")
+    }
+}
+
+impl Output for Sender<Msg<Value>> {
+    fn send(&mut self, msg: Msg<Value>) -> eyre::Result<()> {
+        Sender::send(self, msg).context("output channel closed")
+    }
+}
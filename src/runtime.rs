@@ -0,0 +1,55 @@
+use std::{
+    io::BufRead,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Instant,
+};
+
+use eyre::Context;
+use serde::de::DeserializeOwned;
+
+use crate::{Driver, JsonLines, Node};
+
+pub fn main_loop<S, N, P, E>(init_state: S) -> eyre::Result<()>
+where
+    N: Node<S, P, E>,
+    P: DeserializeOwned,
+    E: Clone,
+{
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin().lock();
+        for line in stdin.lines() {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut driver = Driver::<S, N, P, E>::new(init_state, JsonLines(std::io::stdout().lock()));
+
+    loop {
+        let line = match driver.next_deadline() {
+            Some(deadline) => {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => Some(line),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            None => match rx.recv() {
+                Ok(line) => Some(line),
+                Err(_) => break,
+            },
+        };
+
+        driver.fire(Instant::now())?;
+
+        let Some(line) = line else {
+            continue;
+        };
+        let line = line.context("STDIN::failed to read line")?;
+        driver.handle_line(&line, Instant::now())?;
+    }
+
+    driver.shutdown(Instant::now())
+}
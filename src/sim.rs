@@ -0,0 +1,622 @@
+//! A deterministic, single-threaded stand-in for Maelstrom: runs a cluster of
+//! [`Node`]s in-process over a virtual network with seeded latency and loss,
+//! and a virtual clock that jumps straight from one event to the next.
+//!
+//! Every choice the simulator makes comes from [`SimConfig::seed`], so the
+//! same seed gives the same [`Sim::history`] as long as the nodes themselves
+//! are deterministic (e.g. they iterate `BTreeMap`s rather than `HashMap`s
+//! when deciding what to send). Faults such as partitions and crashes are
+//! injected with [`Sim::apply`]. Nodes that store data in Maelstrom's KV
+//! services can be given in-process ones with [`Sim::add_service`].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use eyre::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{kv::Service, Body, Driver, Error, Init, InitPayload, Msg, Node, Rng, RpcError};
+
+mod nemesis;
+
+pub use nemesis::Fault;
+use nemesis::Network;
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    pub seed: u64,
+    /// Each message takes a latency drawn uniformly from
+    /// `min_latency..=max_latency`, so messages get reordered whenever the
+    /// range is not empty.
+    pub min_latency: Duration,
+    pub max_latency: Duration,
+    /// Probability that a message between two nodes is lost. Traffic to and
+    /// from clients is never lost, like in Maelstrom.
+    pub loss: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(10),
+            loss: 0.0,
+        }
+    }
+}
+
+/// What happened during a run, in order.
+#[derive(Debug, Clone)]
+pub enum Record {
+    Sent { at: Duration, msg: Msg<Value> },
+    Delivered { at: Duration, msg: Msg<Value> },
+    Dropped { at: Duration, msg: Msg<Value> },
+    Fault { at: Duration, fault: Fault },
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (at, what, msg) = match self {
+            Record::Sent { at, msg } => (at, "sent", msg),
+            Record::Delivered { at, msg } => (at, "delivered", msg),
+            Record::Dropped { at, msg } => (at, "dropped", msg),
+            Record::Fault { at, fault } => {
+                return write!(f, "{:>10}us fault {fault}", at.as_micros());
+            }
+        };
+        let msg = serde_json::to_string(msg).map_err(|_| fmt::Error)?;
+        write!(f, "{:>10}us {what} {msg}", at.as_micros())
+    }
+}
+
+struct SimNode<S, N, P, E> {
+    driver: Driver<S, N, P, E>,
+    outbox: Receiver<Msg<Value>>,
+    crashed: bool,
+}
+
+impl<S, N, P, E> SimNode<S, N, P, E>
+where
+    N: Node<S, P, E>,
+    P: DeserializeOwned,
+    E: Clone,
+{
+    fn new(state: S) -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            driver: Driver::new(state, tx),
+            outbox: rx,
+            crashed: false,
+        }
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        if self.crashed {
+            return None;
+        }
+        self.driver.next_deadline()
+    }
+}
+
+pub struct Sim<S, N, P, E = ()> {
+    config: SimConfig,
+    /// What restarted nodes start over from.
+    state: S,
+    rng: Rng,
+    epoch: Instant,
+    now: Duration,
+    nodes: BTreeMap<String, SimNode<S, N, P, E>>,
+    services: BTreeMap<String, Service>,
+    network: Network,
+    /// Messages in flight, by delivery time and then by send order.
+    in_flight: BTreeMap<(Duration, u64), Msg<Value>>,
+    sent: u64,
+    next_client_msg_id: usize,
+    /// Everything nodes sent to clients.
+    inbox: Vec<Msg<Value>>,
+    history: Vec<Record>,
+}
+
+impl<S, N, P, E> Sim<S, N, P, E>
+where
+    S: Clone,
+    N: Node<S, P, E>,
+    P: DeserializeOwned,
+    E: Clone,
+{
+    /// Starts `n` nodes named `n0`, `n1`, ... and initializes them.
+    pub fn new(config: SimConfig, n: usize, state: S) -> eyre::Result<Self> {
+        let node_ids: Vec<String> = (0..n).map(|i| format!("n{i}")).collect();
+        let mut sim = Self {
+            rng: Rng::new(config.seed),
+            config,
+            state: state.clone(),
+            epoch: Instant::now(),
+            now: Duration::ZERO,
+            nodes: BTreeMap::new(),
+            services: BTreeMap::new(),
+            network: Network::default(),
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            next_client_msg_id: 0,
+            inbox: Vec::new(),
+            history: Vec::new(),
+        };
+
+        for id in &node_ids {
+            sim.nodes.insert(id.clone(), SimNode::new(state.clone()));
+        }
+
+        let mut inits = Vec::new();
+        for id in &node_ids {
+            inits.push((id, sim.init(id)?));
+        }
+        // Init and its reply each take up to the highest latency.
+        let timeout = Duration::from_secs(1) + 2 * sim.config.max_latency;
+        for (id, msg_id) in inits {
+            let reply = sim.wait_for("c0", msg_id, timeout)?;
+            reply
+                .parse::<InitPayload>()
+                .with_context(|| format!("{id} did not answer init with init_ok"))?;
+        }
+
+        Ok(sim)
+    }
+
+    /// Answers messages sent to the service's name from now on. Services
+    /// are reachable from every node whatever the faults, like in Maelstrom.
+    pub fn add_service(&mut self, service: Service) {
+        self.services.insert(service.name().to_owned(), service);
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = &str> + '_ {
+        self.nodes.keys().map(String::as_str)
+    }
+
+    /// The state of a node, for assertions.
+    pub fn node(&self, id: &str) -> Option<&N> {
+        self.nodes.get(id).and_then(|n| n.driver.node())
+    }
+
+    pub fn history(&self) -> &[Record] {
+        &self.history
+    }
+
+    /// Every message nodes have sent to clients so far.
+    pub fn inbox(&self) -> &[Msg<Value>] {
+        &self.inbox
+    }
+
+    /// Sends `payload` from `client` to node `dst`, returning its msg_id.
+    pub fn request<Q: Serialize>(
+        &mut self,
+        client: &str,
+        dst: &str,
+        payload: Q,
+    ) -> eyre::Result<usize> {
+        let msg_id = self.next_client_msg_id;
+        self.next_client_msg_id += 1;
+
+        let payload = serde_json::to_value(payload).context("Serialize::serialize failed")?;
+        self.route(Msg {
+            src: client.to_owned(),
+            dst: dst.to_owned(),
+            body: Body {
+                id: Some(msg_id),
+                in_reply_to: None,
+                payload,
+            },
+        });
+
+        Ok(msg_id)
+    }
+
+    /// The reply `client` got to its request `msg_id`, if it arrived yet.
+    pub fn reply(&self, client: &str, msg_id: usize) -> Option<&Msg<Value>> {
+        self.inbox
+            .iter()
+            .find(|m| m.dst == client && m.body.in_reply_to == Some(msg_id))
+    }
+
+    /// Runs until `client` gets a reply to `msg_id`, or `timeout` of virtual
+    /// time has passed.
+    pub fn wait_for(
+        &mut self,
+        client: &str,
+        msg_id: usize,
+        timeout: Duration,
+    ) -> eyre::Result<Msg<Value>> {
+        let deadline = self.now + timeout;
+        loop {
+            if let Some(reply) = self.reply(client, msg_id) {
+                return Ok(reply.clone());
+            }
+            match self.next_event() {
+                Some(at) if at <= deadline => self.step()?,
+                _ => {
+                    self.now = self.now.max(deadline);
+                    return Err(RpcError::Timeout.into());
+                }
+            }
+        }
+    }
+
+    /// Sends a request and runs until its reply arrives. An `error` reply is
+    /// returned as an [`RpcError::Maelstrom`].
+    pub fn call<Q: Serialize, R: DeserializeOwned>(
+        &mut self,
+        client: &str,
+        dst: &str,
+        payload: Q,
+        timeout: Duration,
+    ) -> eyre::Result<Msg<R>> {
+        let msg_id = self.request(client, dst, payload)?;
+        let reply = self.wait_for(client, msg_id, timeout)?;
+        if Error::is_error_payload(&reply.body.payload) {
+            let error: Error =
+                serde_json::from_value(reply.body.payload).map_err(RpcError::Malformed)?;
+            return Err(RpcError::Maelstrom(error).into());
+        }
+        Ok(reply.parse().map_err(RpcError::Malformed)?)
+    }
+
+    /// Injects `fault` now and records it in the history.
+    pub fn apply(&mut self, fault: Fault) -> eyre::Result<()> {
+        let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
+        let fault = fault.resolve(&node_ids, &mut self.rng);
+
+        match &fault {
+            Fault::Partition(groups) => self.network.partition(groups),
+            Fault::Lossy { links, loss } => self.network.make_lossy(links, *loss),
+            Fault::Heal => self.network.heal(),
+            Fault::Crash(id) => {
+                let node = self
+                    .nodes
+                    .get_mut(id)
+                    .ok_or_else(|| eyre::eyre!("cannot crash unknown node {id}"))?;
+                node.crashed = true;
+            }
+            Fault::Restart {
+                node: id,
+                lose_state,
+            } => {
+                let node = self
+                    .nodes
+                    .get_mut(id)
+                    .ok_or_else(|| eyre::eyre!("cannot restart unknown node {id}"))?;
+                if *lose_state {
+                    *node = SimNode::new(self.state.clone());
+                    self.init(&id.clone())?;
+                } else {
+                    node.crashed = false;
+                }
+            }
+            Fault::PartitionHalves
+            | Fault::Isolate(_)
+            | Fault::Bridge
+            | Fault::FlakyLinks { .. } => {
+                unreachable!("random faults are resolved above")
+            }
+        }
+
+        self.history.push(Record::Fault {
+            at: self.now,
+            fault,
+        });
+        Ok(())
+    }
+
+    /// Runs everything scheduled within the next `duration` of virtual time.
+    pub fn run_for(&mut self, duration: Duration) -> eyre::Result<()> {
+        let until = self.now + duration;
+        while self.next_event().is_some_and(|at| at <= until) {
+            self.step()?;
+        }
+        self.now = until;
+        Ok(())
+    }
+
+    /// When the next message is delivered or timer fires, if ever.
+    pub fn next_event(&self) -> Option<Duration> {
+        let delivery = self.in_flight.keys().next().map(|(at, _)| *at);
+        let timer = self
+            .nodes
+            .values()
+            .filter_map(SimNode::next_deadline)
+            .min()
+            .map(|at| at.saturating_duration_since(self.epoch));
+        delivery.into_iter().chain(timer).min()
+    }
+
+    /// Advances the clock to the next event and handles it. Timers go before
+    /// deliveries scheduled for the same instant.
+    pub fn step(&mut self) -> eyre::Result<()> {
+        let Some(at) = self.next_event() else {
+            return Ok(());
+        };
+        self.now = self.now.max(at);
+        let now = self.epoch + self.now;
+
+        let due: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.next_deadline().is_some_and(|d| d <= now))
+            .map(|(id, _)| id.clone())
+            .collect();
+        if !due.is_empty() {
+            for id in due {
+                let node = self.nodes.get_mut(&id).expect("id was just listed");
+                node.driver
+                    .fire(now)
+                    .with_context(|| format!("{id} failed firing timers"))?;
+                self.flush(&id);
+            }
+            return Ok(());
+        }
+
+        let (_, msg) = self
+            .in_flight
+            .pop_first()
+            .expect("next event is a delivery");
+        let dst = msg.dst.clone();
+        let lost = match self.nodes.get(&dst) {
+            Some(node) if node.crashed => true,
+            Some(_) if self.nodes.contains_key(&msg.src) => !self.network.connected(&msg.src, &dst),
+            _ => false,
+        };
+        if lost {
+            self.history.push(Record::Dropped { at: self.now, msg });
+            return Ok(());
+        }
+
+        self.history.push(Record::Delivered {
+            at: self.now,
+            msg: msg.clone(),
+        });
+        match self.nodes.get_mut(&dst) {
+            Some(node) => {
+                node.driver
+                    .handle(msg, now)
+                    .with_context(|| format!("{dst} failed handling a message"))?;
+                self.flush(&dst);
+            }
+            None => match self.services.get_mut(&dst) {
+                Some(service) => {
+                    if let Some(reply) = service.handle(msg, now) {
+                        self.route(reply);
+                    }
+                }
+                None => self.inbox.push(msg),
+            },
+        }
+
+        Ok(())
+    }
+
+    fn init(&mut self, id: &str) -> eyre::Result<usize> {
+        let init = InitPayload::Init(Init {
+            node_id: id.to_owned(),
+            node_ids: self.nodes.keys().cloned().collect(),
+        });
+        self.request("c0", id, init)
+    }
+
+    /// Puts everything `id` wrote onto the network.
+    fn flush(&mut self, id: &str) {
+        let msgs: Vec<_> = self.nodes[id].outbox.try_iter().collect();
+        for msg in msgs {
+            self.route(msg);
+        }
+    }
+
+    fn route(&mut self, msg: Msg<Value>) {
+        self.history.push(Record::Sent {
+            at: self.now,
+            msg: msg.clone(),
+        });
+
+        let between_nodes = self.nodes.contains_key(&msg.src) && self.nodes.contains_key(&msg.dst);
+        let loss = self
+            .network
+            .loss(&msg.src, &msg.dst)
+            .unwrap_or(self.config.loss);
+        if between_nodes && self.rng.chance(loss) {
+            self.history.push(Record::Dropped { at: self.now, msg });
+            return;
+        }
+
+        let latency = self
+            .rng
+            .duration(self.config.min_latency, self.config.max_latency);
+        self.in_flight.insert((self.now + latency, self.sent), msg);
+        self.sent += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::{Ctx, Event};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    #[serde(rename_all = "snake_case")]
+    enum Payload {
+        Add { value: u64 },
+        AddOk,
+        Gossip { values: BTreeSet<u64> },
+        GossipOk,
+    }
+
+    #[derive(Debug, Clone)]
+    struct Tick;
+
+    /// Adds values to a set and keeps sending the whole set to every peer.
+    struct Flood {
+        values: BTreeSet<u64>,
+    }
+
+    impl Node<(), Payload, Tick> for Flood {
+        fn from_init(_state: (), _init: Init, ctx: &mut Ctx<Self, Tick>) -> eyre::Result<Self> {
+            ctx.every(Duration::from_millis(50), Tick);
+            Ok(Flood {
+                values: BTreeSet::new(),
+            })
+        }
+
+        fn step(
+            &mut self,
+            input: Event<Payload, Tick>,
+            ctx: &mut Ctx<Self, Tick>,
+        ) -> eyre::Result<()> {
+            let input = match input {
+                Event::Message(input) => input,
+                Event::Timer(Tick) => {
+                    let peers: Vec<String> = ctx.peers().map(String::from).collect();
+                    for peer in peers {
+                        let values = self.values.clone();
+                        ctx.rpc(
+                            peer,
+                            Payload::Gossip { values },
+                            Duration::from_millis(20),
+                            |_, _: Result<Msg<Payload>, RpcError>, _| Ok(()),
+                        )?;
+                    }
+                    return Ok(());
+                }
+                Event::Eof => return Ok(()),
+            };
+            match &input.body.payload {
+                Payload::Add { value } => {
+                    self.values.insert(*value);
+                    ctx.reply(&input, Payload::AddOk)?;
+                }
+                Payload::Gossip { values } => {
+                    self.values.extend(values);
+                    ctx.reply(&input, Payload::GossipOk)?;
+                }
+                Payload::AddOk | Payload::GossipOk => {}
+            }
+            Ok(())
+        }
+    }
+
+    fn run(seed: u64) -> Vec<String> {
+        let config = SimConfig {
+            seed,
+            min_latency: Duration::from_millis(1),
+            max_latency: Duration::from_millis(30),
+            loss: 0.2,
+        };
+        let mut sim: Sim<(), Flood, Payload, Tick> = Sim::new(config, 3, ()).unwrap();
+        for value in 0..10 {
+            let node = format!("n{}", value % 3);
+            sim.request("c1", &node, Payload::Add { value }).unwrap();
+            sim.run_for(Duration::from_millis(20)).unwrap();
+        }
+        sim.apply(Fault::PartitionHalves).unwrap();
+        sim.run_for(Duration::from_millis(300)).unwrap();
+        sim.apply(Fault::Heal).unwrap();
+        sim.run_for(Duration::from_millis(300)).unwrap();
+
+        for id in ["n0", "n1", "n2"] {
+            assert_eq!(sim.node(id).unwrap().values.len(), 10, "{id} converged");
+        }
+        sim.history().iter().map(Record::to_string).collect()
+    }
+
+    #[test]
+    fn same_seed_same_history() {
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert_ne!(first, run(8));
+    }
+
+    #[test]
+    fn records_faults_as_resolved() {
+        let config = SimConfig {
+            seed: 3,
+            ..SimConfig::default()
+        };
+        let mut sim: Sim<(), Flood, Payload, Tick> = Sim::new(config, 4, ()).unwrap();
+        sim.request("c1", "n0", Payload::Add { value: 1 }).unwrap();
+        sim.run_for(Duration::from_millis(100)).unwrap();
+
+        sim.apply(Fault::Isolate("n2".to_owned())).unwrap();
+        sim.request("c1", "n0", Payload::Add { value: 2 }).unwrap();
+        sim.run_for(Duration::from_millis(100)).unwrap();
+        assert!(!sim.node("n2").unwrap().values.contains(&2), "isolated");
+        sim.apply(Fault::Bridge).unwrap();
+        sim.apply(Fault::FlakyLinks {
+            fraction: 1.0,
+            loss: 1.0,
+        })
+        .unwrap();
+        sim.apply(Fault::Heal).unwrap();
+
+        sim.apply(Fault::Crash("n1".to_owned())).unwrap();
+        sim.request("c1", "n0", Payload::Add { value: 3 }).unwrap();
+        sim.run_for(Duration::from_millis(100)).unwrap();
+        sim.apply(Fault::Restart {
+            node: "n1".to_owned(),
+            lose_state: false,
+        })
+        .unwrap();
+        // Nothing reached n1 while it was down, and it kept what it had.
+        let values: Vec<_> = sim.node("n1").unwrap().values.iter().copied().collect();
+        assert_eq!(values, [1, 2]);
+        sim.run_for(Duration::from_millis(100)).unwrap();
+        for id in ["n0", "n1", "n2", "n3"] {
+            assert_eq!(sim.node(id).unwrap().values.len(), 3, "{id} converged");
+        }
+
+        let faults: Vec<_> = sim
+            .history()
+            .iter()
+            .filter_map(|record| match record {
+                Record::Fault { fault, .. } => Some(fault.clone()),
+                _ => None,
+            })
+            .collect();
+        let [isolate, bridge, lossy, Fault::Heal, crash, restart] = &faults[..] else {
+            panic!("faults recorded as they happened: {faults:?}");
+        };
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            *isolate,
+            Fault::Partition(vec![ids(&["n2"]), ids(&["n0", "n1", "n3"])])
+        );
+        let Fault::Partition(groups) = bridge else {
+            panic!("bridge recorded as {bridge}");
+        };
+        let mut sizes: Vec<_> = groups.iter().map(Vec::len).collect();
+        sizes.sort();
+        assert_eq!(sizes, [2, 3], "two halves and the bridge in both");
+        let Fault::Lossy { links, loss: 1.0 } = lossy else {
+            panic!("flaky links recorded as {lossy}");
+        };
+        assert_eq!(links.len(), 12, "every directed link");
+        assert_eq!(*crash, Fault::Crash("n1".to_owned()));
+        assert_eq!(
+            *restart,
+            Fault::Restart {
+                node: "n1".to_owned(),
+                lose_state: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use crate::Rng;
+
+/// A fault to inject with [`super::Sim::apply`].
+///
+/// The random variants are resolved with the simulation's seed and recorded
+/// in the history as the concrete fault they turned into, so a run can be
+/// read back (and replayed) without knowing the rng.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// Nodes can only talk to nodes they share at least one group with;
+    /// nodes in no group are cut off from everyone. Groups may overlap.
+    Partition(Vec<Vec<String>>),
+    /// Splits the cluster into two random halves.
+    PartitionHalves,
+    /// Cuts one node off from all the others.
+    Isolate(String),
+    /// Two random halves that cannot talk to each other, but can both talk to
+    /// one node in the middle.
+    Bridge,
+    /// Messages on the given directed links are lost with probability `loss`.
+    Lossy {
+        links: Vec<(String, String)>,
+        loss: f64,
+    },
+    /// Picks each directed link with probability `fraction` and makes it lose
+    /// messages with probability `loss`.
+    FlakyLinks { fraction: f64, loss: f64 },
+    /// Stops a node: it handles no messages and fires no timers, and whatever
+    /// reaches it meanwhile is lost.
+    Crash(String),
+    /// Brings a crashed node back. With `lose_state` it starts over from its
+    /// initial state and is sent init again, as if its process was killed.
+    Restart { node: String, lose_state: bool },
+    /// Undoes every partition and lossy link. Crashed nodes stay crashed.
+    Heal,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Partition(groups) => write!(f, "partition {groups:?}"),
+            Fault::PartitionHalves => write!(f, "partition-halves"),
+            Fault::Isolate(node) => write!(f, "isolate {node}"),
+            Fault::Bridge => write!(f, "bridge"),
+            Fault::Lossy { links, loss } => write!(f, "lossy {loss} {links:?}"),
+            Fault::FlakyLinks { fraction, loss } => write!(f, "flaky-links {fraction} {loss}"),
+            Fault::Crash(node) => write!(f, "crash {node}"),
+            Fault::Restart { node, lose_state } => {
+                write!(f, "restart {node} lose_state={lose_state}")
+            }
+            Fault::Heal => write!(f, "heal"),
+        }
+    }
+}
+
+impl Fault {
+    /// Turns the random variants into concrete ones.
+    pub(crate) fn resolve(self, node_ids: &[String], rng: &mut Rng) -> Fault {
+        match self {
+            Fault::PartitionHalves => {
+                let mut ids = node_ids.to_vec();
+                rng.shuffle(&mut ids);
+                let rest = ids.split_off(ids.len() / 2);
+                Fault::Partition(vec![sorted(ids), sorted(rest)])
+            }
+            Fault::Isolate(node) => {
+                let rest = node_ids.iter().filter(|id| **id != node).cloned().collect();
+                Fault::Partition(vec![vec![node], rest])
+            }
+            Fault::Bridge => {
+                let mut ids = node_ids.to_vec();
+                rng.shuffle(&mut ids);
+                let mut rest = ids.split_off(ids.len() / 2);
+                let Some(bridge) = rest.pop() else {
+                    return Fault::Partition(vec![ids]);
+                };
+                ids.push(bridge.clone());
+                rest.push(bridge);
+                Fault::Partition(vec![sorted(ids), sorted(rest)])
+            }
+            Fault::FlakyLinks { fraction, loss } => {
+                let mut links = Vec::new();
+                for src in node_ids {
+                    for dst in node_ids.iter().filter(|dst| *dst != src) {
+                        if rng.chance(fraction) {
+                            links.push((src.clone(), dst.clone()));
+                        }
+                    }
+                }
+                Fault::Lossy { links, loss }
+            }
+            fault => fault,
+        }
+    }
+}
+
+fn sorted(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids
+}
+
+/// The network faults currently in effect between nodes.
+#[derive(Debug, Default)]
+pub(crate) struct Network {
+    groups: Option<Vec<BTreeSet<String>>>,
+    lossy: BTreeMap<(String, String), f64>,
+}
+
+impl Network {
+    pub(crate) fn partition(&mut self, groups: &[Vec<String>]) {
+        self.groups = Some(
+            groups
+                .iter()
+                .map(|group| group.iter().cloned().collect())
+                .collect(),
+        );
+    }
+
+    pub(crate) fn make_lossy(&mut self, links: &[(String, String)], loss: f64) {
+        for link in links {
+            self.lossy.insert(link.clone(), loss);
+        }
+    }
+
+    pub(crate) fn heal(&mut self) {
+        self.groups = None;
+        self.lossy.clear();
+    }
+
+    pub(crate) fn connected(&self, src: &str, dst: &str) -> bool {
+        let Some(groups) = &self.groups else {
+            return true;
+        };
+        groups
+            .iter()
+            .any(|group| group.contains(src) && group.contains(dst))
+    }
+
+    pub(crate) fn loss(&self, src: &str, dst: &str) -> Option<f64> {
+        self.lossy.get(&(src.to_owned(), dst.to_owned())).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{i}")).collect()
+    }
+
+    fn network(fault: &Fault) -> Network {
+        let mut network = Network::default();
+        match fault {
+            Fault::Partition(groups) => network.partition(groups),
+            Fault::Lossy { links, loss } => network.make_lossy(links, *loss),
+            fault => panic!("{fault} is not resolved"),
+        }
+        network
+    }
+
+    #[test]
+    fn isolate() {
+        let fault = Fault::Isolate("n1".to_owned()).resolve(&ids(4), &mut Rng::new(1));
+        assert_eq!(
+            fault,
+            Fault::Partition(vec![
+                vec!["n1".to_owned()],
+                vec!["n0".to_owned(), "n2".to_owned(), "n3".to_owned()],
+            ])
+        );
+        let network = network(&fault);
+        assert!(!network.connected("n1", "n0"));
+        assert!(!network.connected("n3", "n1"));
+        assert!(network.connected("n0", "n3"));
+    }
+
+    #[test]
+    fn bridge() {
+        for seed in 0..20 {
+            let fault = Fault::Bridge.resolve(&ids(5), &mut Rng::new(seed));
+            let Fault::Partition(groups) = &fault else {
+                panic!("bridge resolved to {fault}");
+            };
+            let [left, right] = &groups[..] else {
+                panic!("bridge resolved to {fault}");
+            };
+            let shared: Vec<_> = left.iter().filter(|id| right.contains(id)).collect();
+            assert_eq!(shared.len(), 1, "one bridge in {fault}");
+            assert_eq!((left.len(), right.len()), (3, 3), "{fault}");
+
+            let network = network(&fault);
+            let bridge = shared[0];
+            for id in ids(5) {
+                assert!(network.connected(bridge, &id), "{bridge} reaches {id}");
+            }
+            let only_left = left.iter().find(|id| *id != bridge).unwrap();
+            let only_right = right.iter().find(|id| *id != bridge).unwrap();
+            assert!(!network.connected(only_left, only_right), "{fault}");
+        }
+    }
+
+    #[test]
+    fn flaky_links() {
+        let all = Fault::FlakyLinks {
+            fraction: 1.0,
+            loss: 0.5,
+        };
+        let Fault::Lossy { links, loss } = all.resolve(&ids(3), &mut Rng::new(3)) else {
+            panic!("flaky links resolve to lossy ones");
+        };
+        assert_eq!(links.len(), 6, "every directed link");
+        assert_eq!(loss, 0.5);
+        assert!(!links.iter().any(|(src, dst)| src == dst));
+
+        let none = Fault::FlakyLinks {
+            fraction: 0.0,
+            loss: 0.5,
+        };
+        assert_eq!(
+            none.resolve(&ids(3), &mut Rng::new(3)),
+            Fault::Lossy {
+                links: vec![],
+                loss: 0.5
+            }
+        );
+
+        let some = Fault::FlakyLinks {
+            fraction: 0.5,
+            loss: 0.25,
+        };
+        let fault = some.clone().resolve(&ids(5), &mut Rng::new(4));
+        assert_eq!(fault, some.resolve(&ids(5), &mut Rng::new(4)), "seeded");
+        let Fault::Lossy { links, .. } = &fault else {
+            panic!("flaky links resolve to lossy ones");
+        };
+        assert!((1..20).contains(&links.len()), "{fault}");
+        let network = network(&fault);
+        for src in ids(5) {
+            for dst in ids(5).into_iter().filter(|dst| *dst != src) {
+                let flaky = links.contains(&(src.clone(), dst.clone()));
+                let expected = flaky.then_some(0.25);
+                assert_eq!(network.loss(&src, &dst), expected, "{src} -> {dst}");
+            }
+        }
+    }
+
+    #[test]
+    fn concrete_faults_stay_as_they_are() {
+        let faults = [
+            Fault::Partition(vec![vec!["n0".to_owned()]]),
+            Fault::Lossy {
+                links: vec![("n0".to_owned(), "n1".to_owned())],
+                loss: 0.1,
+            },
+            Fault::Crash("n0".to_owned()),
+            Fault::Restart {
+                node: "n0".to_owned(),
+                lose_state: false,
+            },
+            Fault::Heal,
+        ];
+        for fault in faults {
+            assert_eq!(fault.clone().resolve(&ids(3), &mut Rng::new(5)), fault);
+        }
+    }
+}
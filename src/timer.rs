@@ -0,0 +1,75 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(usize);
+
+struct Timer<E> {
+    event: E,
+    period: Option<Duration>,
+}
+
+/// One-shot and periodic timers, ordered by deadline and then by the order
+/// they were scheduled in.
+pub(crate) struct Timers<E> {
+    next_id: usize,
+    queue: BTreeMap<(Instant, TimerId), Timer<E>>,
+}
+
+impl<E> Timers<E> {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_id: 0,
+            queue: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn schedule(&mut self, at: Instant, event: E, period: Option<Duration>) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.queue.insert((at, id), Timer { event, period });
+        id
+    }
+
+    pub(crate) fn cancel(&mut self, id: TimerId) -> bool {
+        let Some(key) = self.queue.keys().find(|(_, tid)| *tid == id).copied() else {
+            return false;
+        };
+        self.queue.remove(&key).is_some()
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.queue.keys().next().map(|(at, _)| *at)
+    }
+
+    /// Pops the earliest timer due at `now`, re-arming it if it is periodic.
+    pub(crate) fn pop_due(&mut self, now: Instant) -> Option<E>
+    where
+        E: Clone,
+    {
+        let (&(at, id), _) = self.queue.first_key_value()?;
+        if at > now {
+            return None;
+        }
+
+        let timer = self
+            .queue
+            .remove(&(at, id))
+            .expect("key was just looked up");
+        let Some(period) = timer.period else {
+            return Some(timer.event);
+        };
+
+        // Skip the ticks we slept through instead of firing them back to back.
+        let mut next = at + period;
+        if next <= now {
+            next = now + period;
+        }
+        let event = timer.event.clone();
+        self.queue.insert((next, id), timer);
+
+        Some(event)
+    }
+}
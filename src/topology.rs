@@ -0,0 +1,365 @@
+//! Ways to lay out a cluster, in the shape of the broadcast workload's
+//! `topology` message: each node mapped to the nodes it talks to.
+//!
+//! Links always go both ways, and nodes are placed in the order of
+//! `node_ids`, so the same ids give the same layout. [`diameter`],
+//! [`max_degree`] and [`min_cut`] help to pick one: the diameter bounds how
+//! many hops a value needs, the degree how many messages a node sends per
+//! value, and the min cut how many links can fail before the cluster splits.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use eyre::bail;
+
+use crate::Rng;
+
+pub type Topology = HashMap<String, Vec<String>>;
+
+fn empty(node_ids: &[String]) -> Topology {
+    node_ids.iter().map(|id| (id.clone(), Vec::new())).collect()
+}
+
+fn link(topology: &mut Topology, a: &str, b: &str) {
+    if a == b {
+        return;
+    }
+    for (from, to) in [(a, b), (b, a)] {
+        let neighbours = topology.entry(from.to_owned()).or_default();
+        if !neighbours.iter().any(|n| n == to) {
+            neighbours.push(to.to_owned());
+        }
+    }
+}
+
+/// Each node linked to the ones before and after it.
+pub fn line(node_ids: &[String]) -> Topology {
+    let mut topology = empty(node_ids);
+    for pair in node_ids.windows(2) {
+        link(&mut topology, &pair[0], &pair[1]);
+    }
+    topology
+}
+
+/// A [`line`] with its ends joined.
+pub fn ring(node_ids: &[String]) -> Topology {
+    let mut topology = line(node_ids);
+    if let (Some(first), Some(last)) = (node_ids.first(), node_ids.last()) {
+        link(&mut topology, first, last);
+    }
+    topology
+}
+
+/// Maelstrom's default: a grid as close to square as the node count allows,
+/// each node linked to its horizontal and vertical neighbours.
+pub fn grid(node_ids: &[String]) -> Topology {
+    let width = (node_ids.len() as f64).sqrt().ceil().max(1.0) as usize;
+    let mut topology = empty(node_ids);
+    for (i, id) in node_ids.iter().enumerate() {
+        if i % width + 1 < width && i + 1 < node_ids.len() {
+            link(&mut topology, id, &node_ids[i + 1]);
+        }
+        if i + width < node_ids.len() {
+            link(&mut topology, id, &node_ids[i + width]);
+        }
+    }
+    topology
+}
+
+/// A spanning tree where every node has up to `k` children, rooted at the
+/// first node. Needs `k > 0`.
+pub fn tree(node_ids: &[String], k: usize) -> eyre::Result<Topology> {
+    if k == 0 {
+        bail!("a tree needs at least one child per node");
+    }
+    let mut topology = empty(node_ids);
+    for (i, id) in node_ids.iter().enumerate().skip(1) {
+        link(&mut topology, &node_ids[(i - 1) / k], id);
+    }
+    Ok(topology)
+}
+
+/// Every node linked to the first one and to nothing else.
+pub fn star(node_ids: &[String]) -> Topology {
+    let mut topology = empty(node_ids);
+    if let Some((center, rest)) = node_ids.split_first() {
+        for id in rest {
+            link(&mut topology, center, id);
+        }
+    }
+    topology
+}
+
+/// The first `hubs` nodes linked to each other and to every other node, and
+/// the other nodes (the spokes) to nothing else. One hub is a [`star`]; more
+/// of them let the spokes survive losing all but one.
+pub fn hub_and_spoke(node_ids: &[String], hubs: usize) -> Topology {
+    let hubs = hubs.max(1).min(node_ids.len());
+    let mut topology = empty(node_ids);
+    for (i, hub) in node_ids[..hubs].iter().enumerate() {
+        for other in &node_ids[i + 1..] {
+            link(&mut topology, hub, other);
+        }
+    }
+    topology
+}
+
+/// A random graph where every node has exactly `k` neighbours. Needs
+/// `k < node_ids.len()` and an even `k * node_ids.len()`.
+pub fn random_regular(node_ids: &[String], k: usize, rng: &mut Rng) -> eyre::Result<Topology> {
+    let n = node_ids.len();
+    if k >= n.max(1) || !(n * k).is_multiple_of(2) {
+        bail!("no {k}-regular graph on {n} nodes");
+    }
+
+    // Links random pairs of nodes that still need neighbours and are not
+    // linked yet, starting over whenever that paints itself into a corner.
+    // Rarely takes more than a few attempts.
+    for _ in 0..1000 {
+        let mut links: BTreeSet<(usize, usize)> = BTreeSet::new();
+        let mut remaining = vec![k; n];
+        loop {
+            let candidates: Vec<(usize, usize)> = (0..n)
+                .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+                .filter(|&(a, b)| remaining[a] > 0 && remaining[b] > 0)
+                .filter(|pair| !links.contains(pair))
+                .collect();
+            if candidates.is_empty() {
+                break;
+            }
+            let (a, b) = candidates[rng.below(candidates.len() as u64) as usize];
+            links.insert((a, b));
+            remaining[a] -= 1;
+            remaining[b] -= 1;
+        }
+
+        if remaining.iter().all(|r| *r == 0) {
+            let mut topology = empty(node_ids);
+            for (a, b) in links {
+                link(&mut topology, &node_ids[a], &node_ids[b]);
+            }
+            return Ok(topology);
+        }
+    }
+    bail!("gave up looking for a {k}-regular graph on {n} nodes")
+}
+
+/// Every node mentioned, as a neighbour or not, with its outgoing links as
+/// indices.
+fn adjacency(topology: &Topology) -> Vec<Vec<usize>> {
+    let nodes: BTreeSet<&str> = topology
+        .iter()
+        .flat_map(|(id, neighbours)| {
+            std::iter::once(id.as_str()).chain(neighbours.iter().map(String::as_str))
+        })
+        .collect();
+    let index: BTreeMap<&str, usize> = nodes.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+    let mut adjacency = vec![Vec::new(); nodes.len()];
+    for (id, neighbours) in topology {
+        adjacency[index[id.as_str()]] = neighbours.iter().map(|n| index[n.as_str()]).collect();
+    }
+    adjacency
+}
+
+/// Hops from `from` to every node, `None` where there is no path.
+fn distances(adjacency: &[Vec<usize>], from: usize) -> Vec<Option<usize>> {
+    let mut distances = vec![None; adjacency.len()];
+    distances[from] = Some(0);
+    let mut queue = VecDeque::from([from]);
+    while let Some(node) = queue.pop_front() {
+        let hops = distances[node].map(|d| d + 1);
+        for &next in &adjacency[node] {
+            if distances[next].is_none() {
+                distances[next] = hops;
+                queue.push_back(next);
+            }
+        }
+    }
+    distances
+}
+
+/// The most hops any node needs to reach any other, or `None` if some node
+/// cannot reach some other at all.
+pub fn diameter(topology: &Topology) -> Option<usize> {
+    let adjacency = adjacency(topology);
+    let mut diameter = 0;
+    for from in 0..adjacency.len() {
+        for hops in distances(&adjacency, from) {
+            diameter = diameter.max(hops?);
+        }
+    }
+    Some(diameter)
+}
+
+/// The most links any one node has.
+pub fn max_degree(topology: &Topology) -> usize {
+    topology.values().map(Vec::len).max().unwrap_or(0)
+}
+
+/// The fewest links that have to fail for some node to be unable to reach
+/// some other: 0 if that is already the case, 1 for a tree.
+pub fn min_cut(topology: &Topology) -> usize {
+    let adjacency = adjacency(topology);
+    // Any cut separates the first node from some other one, one way or the
+    // other, so the smallest of those flows is the smallest cut.
+    (1..adjacency.len())
+        .flat_map(|other| {
+            [
+                max_flow(&adjacency, 0, other),
+                max_flow(&adjacency, other, 0),
+            ]
+        })
+        .min()
+        .unwrap_or(0)
+}
+
+/// Edmonds-Karp with every link carrying one unit.
+fn max_flow(adjacency: &[Vec<usize>], source: usize, sink: usize) -> usize {
+    let mut capacity: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    let mut edges = vec![BTreeSet::new(); adjacency.len()];
+    for (a, neighbours) in adjacency.iter().enumerate() {
+        for &b in neighbours {
+            *capacity.entry((a, b)).or_default() += 1;
+            edges[a].insert(b);
+            edges[b].insert(a);
+        }
+    }
+
+    let mut flow = 0;
+    loop {
+        let mut parent = vec![None; adjacency.len()];
+        parent[source] = Some(source);
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            for &next in &edges[node] {
+                let left = capacity.get(&(node, next)).copied().unwrap_or(0);
+                if parent[next].is_none() && left > 0 {
+                    parent[next] = Some(node);
+                    queue.push_back(next);
+                }
+            }
+        }
+        if parent[sink].is_none() {
+            return flow;
+        }
+
+        let mut node = sink;
+        while node != source {
+            let prev = parent[node].expect("on the augmenting path");
+            *capacity.entry((prev, node)).or_default() -= 1;
+            *capacity.entry((node, prev)).or_default() += 1;
+            node = prev;
+        }
+        flow += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{i}")).collect()
+    }
+
+    fn neighbours(topology: &Topology, id: &str) -> Vec<String> {
+        let mut neighbours = topology[id].clone();
+        neighbours.sort();
+        neighbours
+    }
+
+    /// Diameter, max degree and min cut.
+    fn shape(topology: &Topology) -> (Option<usize>, usize, usize) {
+        (diameter(topology), max_degree(topology), min_cut(topology))
+    }
+
+    #[test]
+    fn line_and_ring() {
+        let line = line(&ids(5));
+        assert_eq!(neighbours(&line, "n0"), ["n1"]);
+        assert_eq!(neighbours(&line, "n2"), ["n1", "n3"]);
+        assert_eq!(shape(&line), (Some(4), 2, 1));
+
+        let ring = ring(&ids(5));
+        assert_eq!(neighbours(&ring, "n0"), ["n1", "n4"]);
+        assert_eq!(shape(&ring), (Some(2), 2, 2));
+    }
+
+    #[test]
+    fn grid_is_as_square_as_it_gets() {
+        let grid = grid(&ids(9));
+        assert_eq!(neighbours(&grid, "n0"), ["n1", "n3"]);
+        assert_eq!(neighbours(&grid, "n4"), ["n1", "n3", "n5", "n7"]);
+        assert_eq!(neighbours(&grid, "n8"), ["n5", "n7"]);
+        assert_eq!(shape(&grid), (Some(4), 4, 2));
+
+        // A ragged last row: n6 sits under n3, on its own.
+        let grid = super::grid(&ids(7));
+        assert_eq!(neighbours(&grid, "n6"), ["n3"]);
+        assert_eq!(shape(&grid), (Some(4), 3, 1));
+    }
+
+    #[test]
+    fn star_and_trees() {
+        let star = star(&ids(5));
+        assert_eq!(neighbours(&star, "n0"), ["n1", "n2", "n3", "n4"]);
+        assert_eq!(neighbours(&star, "n3"), ["n0"]);
+        assert_eq!(shape(&star), (Some(2), 4, 1));
+
+        let binary = tree(&ids(7), 2).unwrap();
+        assert_eq!(neighbours(&binary, "n0"), ["n1", "n2"]);
+        assert_eq!(neighbours(&binary, "n1"), ["n0", "n3", "n4"]);
+        assert_eq!(neighbours(&binary, "n6"), ["n2"]);
+        assert_eq!(shape(&binary), (Some(4), 3, 1));
+
+        let chain = tree(&ids(4), 1).unwrap();
+        assert_eq!(chain, line(&ids(4)));
+        assert!(tree(&ids(4), 0).is_err());
+    }
+
+    #[test]
+    fn hubs_survive_losing_links() {
+        let hubs = hub_and_spoke(&ids(6), 2);
+        assert_eq!(neighbours(&hubs, "n0"), ["n1", "n2", "n3", "n4", "n5"]);
+        assert_eq!(neighbours(&hubs, "n4"), ["n0", "n1"]);
+        assert_eq!(shape(&hubs), (Some(2), 5, 2));
+
+        let complete = hub_and_spoke(&ids(5), 5);
+        assert_eq!(shape(&complete), (Some(1), 4, 4));
+    }
+
+    #[test]
+    fn random_regular_gives_everyone_k_neighbours() {
+        for seed in 0..10 {
+            let topology = random_regular(&ids(10), 3, &mut Rng::new(seed)).unwrap();
+            for (id, neighbours) in &topology {
+                assert_eq!(neighbours.len(), 3, "{id} in {topology:?}");
+                assert!(!neighbours.contains(id), "{id} linked to itself");
+                for neighbour in neighbours {
+                    assert!(topology[neighbour].contains(id), "{id} - {neighbour}");
+                }
+            }
+        }
+        assert!(random_regular(&ids(5), 3, &mut Rng::new(0)).is_err(), "odd");
+        assert!(
+            random_regular(&ids(4), 4, &mut Rng::new(0)).is_err(),
+            "too few"
+        );
+    }
+
+    #[test]
+    fn disconnected() {
+        let mut topology = line(&ids(4));
+        topology.insert("n9".to_owned(), Vec::new());
+        assert_eq!(shape(&topology), (None, 2, 0));
+
+        // One-way links only count the way they go.
+        let one_way: Topology = [
+            ("n0".to_owned(), vec!["n1".to_owned()]),
+            ("n1".to_owned(), vec![]),
+        ]
+        .into();
+        assert_eq!(diameter(&one_way), None);
+        assert_eq!(min_cut(&one_way), 0);
+    }
+}